@@ -0,0 +1,197 @@
+//! A scriptable MPRIS player for exercising deltatune without a real media player.
+//!
+//! Start a private session bus and point both processes at it:
+//!
+//! ```text
+//! dbus-daemon --session --print-address --fork
+//! cargo run --example fake_mpris_player -- --bus-address <address> --name fake
+//! cargo run -- --mpris-bus-address <address>
+//! ```
+//!
+//! Commands are read from stdin, one per line:
+//!
+//! ```text
+//! play <title> | <artist> [| <art url>]
+//! pause
+//! stop
+//! quit
+//! ```
+
+use dbus::arg::{PropMap, RefArg, Variant};
+use dbus::blocking::Connection;
+use dbus::channel::Channel;
+use dbus::{Message, MessageType};
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::sync::mpsc;
+use std::time::Duration;
+
+const MPRIS_PATH: &str = "/org/mpris/MediaPlayer2";
+const ROOT_IFACE: &str = "org.mpris.MediaPlayer2";
+const PLAYER_IFACE: &str = "org.mpris.MediaPlayer2.Player";
+const PROPERTIES_IFACE: &str = "org.freedesktop.DBus.Properties";
+
+const INTROSPECTION: &str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+  <interface name="org.mpris.MediaPlayer2">
+    <property name="Identity" type="s" access="read"/>
+  </interface>
+  <interface name="org.mpris.MediaPlayer2.Player">
+    <property name="PlaybackStatus" type="s" access="read"/>
+    <property name="Metadata" type="a{sv}" access="read"/>
+  </interface>
+</node>"#;
+
+struct FakePlayer {
+    identity: String,
+    status: String,
+    title: String,
+    artist: String,
+    art_url: String,
+}
+
+impl FakePlayer {
+    fn metadata(&self) -> PropMap {
+        let mut map: PropMap = HashMap::new();
+        map.insert(
+            "mpris:trackid".to_string(),
+            Variant(Box::new(dbus::Path::from("/org/deltatune/fake/track")) as Box<dyn RefArg>),
+        );
+        map.insert("xesam:title".to_string(), Variant(Box::new(self.title.clone())));
+        map.insert(
+            "xesam:artist".to_string(),
+            Variant(Box::new(vec![self.artist.clone()])),
+        );
+        if !self.art_url.is_empty() {
+            map.insert("mpris:artUrl".to_string(), Variant(Box::new(self.art_url.clone())));
+        }
+        map
+    }
+
+    fn properties(&self, interface: &str) -> PropMap {
+        let mut map: PropMap = HashMap::new();
+        match interface {
+            ROOT_IFACE => {
+                map.insert("Identity".to_string(), Variant(Box::new(self.identity.clone())));
+            }
+            PLAYER_IFACE => {
+                map.insert("PlaybackStatus".to_string(), Variant(Box::new(self.status.clone())));
+                map.insert("Metadata".to_string(), Variant(Box::new(self.metadata())));
+            }
+            _ => {}
+        }
+        map
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().collect();
+    let name = get_arg_value(&args, "--name").unwrap_or_else(|| "fake".to_string());
+
+    let connection = match get_arg_value(&args, "--bus-address") {
+        Some(address) => {
+            let mut channel = Channel::open_private(&address)?;
+            channel.register()?;
+            Connection::from(channel)
+        }
+        None => Connection::new_session()?,
+    };
+    connection.request_name(format!("{ROOT_IFACE}.{name}"), false, true, true)?;
+
+    let mut player = FakePlayer {
+        identity: name,
+        status: "Stopped".to_string(),
+        title: String::new(),
+        artist: String::new(),
+        art_url: String::new(),
+    };
+
+    let (command_tx, command_rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines().map_while(Result::ok) {
+            if command_tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    loop {
+        while let Ok(command) = command_rx.try_recv() {
+            let command = command.trim();
+            let (verb, rest) = command.split_once(' ').unwrap_or((command, ""));
+            match verb {
+                "play" => {
+                    let mut fields = rest.split('|').map(str::trim);
+                    player.title = fields.next().unwrap_or("").to_string();
+                    player.artist = fields.next().unwrap_or("").to_string();
+                    player.art_url = fields.next().unwrap_or("").to_string();
+                    player.status = "Playing".to_string();
+                }
+                "pause" => player.status = "Paused".to_string(),
+                "stop" => player.status = "Stopped".to_string(),
+                "quit" => return Ok(()),
+                "" => continue,
+                other => {
+                    eprintln!("Unknown command: {other}");
+                    continue;
+                }
+            }
+
+            let changed = Message::new_signal(MPRIS_PATH, PROPERTIES_IFACE, "PropertiesChanged")?
+                .append3(PLAYER_IFACE, player.properties(PLAYER_IFACE), Vec::<String>::new());
+            let _ = connection.channel().send(changed);
+        }
+
+        let Some(message) = connection
+            .channel()
+            .blocking_pop_message(Duration::from_millis(100))?
+        else {
+            continue;
+        };
+        if message.msg_type() != MessageType::MethodCall {
+            continue;
+        }
+        let _ = connection.channel().send(handle_method_call(&player, &message));
+    }
+}
+
+fn handle_method_call(player: &FakePlayer, message: &Message) -> Message {
+    let member = message.member();
+    match (message.interface().as_deref(), member.as_deref()) {
+        (Some("org.freedesktop.DBus.Introspectable"), Some("Introspect")) => {
+            message.method_return().append1(INTROSPECTION)
+        }
+        (Some(PROPERTIES_IFACE), Some("Get")) => {
+            let Ok((interface, property)) = message.read2::<&str, &str>() else {
+                return invalid_args(message);
+            };
+            match player.properties(interface).remove(property) {
+                Some(value) => message.method_return().append1(value),
+                None => invalid_args(message),
+            }
+        }
+        (Some(PROPERTIES_IFACE), Some("GetAll")) => {
+            let Ok(interface) = message.read1::<&str>() else {
+                return invalid_args(message);
+            };
+            message.method_return().append1(player.properties(interface))
+        }
+        (Some("org.freedesktop.DBus.Peer"), Some("Ping")) => message.method_return(),
+        _ => dbus::channel::default_reply(message).unwrap_or_else(|| invalid_args(message)),
+    }
+}
+
+fn invalid_args(message: &Message) -> Message {
+    message.error(
+        &"org.freedesktop.DBus.Error.InvalidArgs".into(),
+        c"unknown interface or property",
+    )
+}
+
+fn get_arg_value(args: &[String], name: &str) -> Option<String> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|index| args.get(index + 1))
+        .cloned()
+}
@@ -0,0 +1,230 @@
+use crate::{blend_pixel, AlbumArt, ArtFilter};
+use anyhow::{anyhow, Context, Result};
+use image::imageops::{self, FilterType};
+use image::RgbaImage;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// How long a download of remote art may take before it is given up.
+const DOWNLOAD_TIMEOUT_SECS: u32 = 10;
+
+/// Loads the cover art at `url` and shrinks it to the thumbnail `art` asks
+/// for. Failures are logged, since plenty of tracks come without art.
+pub(crate) fn load_album_art(url: &str, art: &AlbumArt) -> Option<RgbaImage> {
+    if url.is_empty() {
+        return None;
+    }
+    let image = read_art_url(url).and_then(|data| Ok(image::load_from_memory(&data)?));
+    match image {
+        Ok(image) => Some(thumbnail(&image.to_rgba8(), art)),
+        Err(err) => {
+            eprintln!("Failed to load album art {}: {err:#}", shorten(url));
+            None
+        }
+    }
+}
+
+/// Reads `file://` and `data:` URLs directly. `http://` and `https://` art is
+/// downloaded into the cache the first time, see `art_cache_path`. This runs
+/// on the art loader thread, so the download does not hold up the overlay.
+fn read_art_url(url: &str) -> Result<Vec<u8>> {
+    if let Some(path) = url.strip_prefix("file://") {
+        // Skip the host part, which is empty or `localhost`.
+        let path = &path[path.find('/').unwrap_or(0)..];
+        let path = PathBuf::from(String::from_utf8(percent_decode(path))?);
+        return fs::read(&path).with_context(|| format!("reading {}", path.display()));
+    }
+    if let Some(data) = url.strip_prefix("data:") {
+        let (header, payload) = data
+            .split_once(',')
+            .ok_or_else(|| anyhow!("data URL without a comma"))?;
+        return if header.ends_with(";base64") {
+            decode_base64(&String::from_utf8(percent_decode(payload))?)
+                .ok_or_else(|| anyhow!("invalid base64"))
+        } else {
+            Ok(percent_decode(payload))
+        };
+    }
+    if url.starts_with("http://") || url.starts_with("https://") {
+        let path = art_cache_path(url).ok_or_else(|| anyhow!("no cache directory"))?;
+        if !path.exists() {
+            download(url, &path)?;
+        }
+        return fs::read(&path).with_context(|| format!("reading {}", path.display()));
+    }
+    Err(anyhow!("unsupported URL scheme"))
+}
+
+/// Where a copy of the art at `url` is kept:
+/// `$XDG_CACHE_HOME/deltatune/art/` (`~/.cache/deltatune/art/`), named after
+/// the 64-bit FNV-1a hash of the URL in hex.
+fn art_cache_path(url: &str) -> Option<PathBuf> {
+    let cache_home = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+    let name = format!("{:016x}", fnv1a(url.as_bytes()));
+    Some(cache_home.join("deltatune/art").join(name))
+}
+
+/// A hash that stays the same across builds, unlike `DefaultHasher`, so the
+/// cache survives upgrades.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Downloads `url` to `path` with curl. The file is only moved into place once
+/// it is complete, so an interrupted download is not mistaken for art.
+fn download(url: &str, path: &Path) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+    }
+    let partial = path.with_extension("part");
+    let status = Command::new("curl")
+        .args(["--silent", "--show-error", "--fail", "--location"])
+        .arg("--max-time")
+        .arg(DOWNLOAD_TIMEOUT_SECS.to_string())
+        .arg("--output")
+        .arg(&partial)
+        .arg("--")
+        .arg(url)
+        .status()
+        .context("running curl")?;
+    if !status.success() {
+        let _ = fs::remove_file(&partial);
+        return Err(anyhow!("curl failed with {status}"));
+    }
+    fs::rename(&partial, path).with_context(|| format!("writing {}", path.display()))
+}
+
+fn percent_decode(text: &str) -> Vec<u8> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = (bytes[index] == b'%')
+            .then(|| text.get(index + 1..index + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            None => {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+    decoded
+}
+
+/// Decodes standard or URL-safe base64, with or without padding.
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(text.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for byte in text.bytes() {
+        let value = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' => break,
+            byte if byte.is_ascii_whitespace() => continue,
+            _ => return None,
+        };
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+    Some(decoded)
+}
+
+/// Crops `image` to a centred square and shrinks it to one pixel per
+/// `pixelation` font pixels.
+fn thumbnail(image: &RgbaImage, art: &AlbumArt) -> RgbaImage {
+    let side = image.width().min(image.height());
+    let square = imageops::crop_imm(
+        image,
+        (image.width() - side) / 2,
+        (image.height() - side) / 2,
+        side,
+        side,
+    )
+    .to_image();
+    let pixels = (art.size / art.pixelation.max(1)).max(1);
+    match art.filter {
+        ArtFilter::Nearest => imageops::resize(&square, pixels, pixels, FilterType::Nearest),
+        ArtFilter::Pixelate => imageops::thumbnail(&square, pixels, pixels),
+    }
+}
+
+/// Draws the thumbnail `size` device pixels big with its top left corner at
+/// `x`, `y`.
+pub(crate) fn draw_album_art(
+    canvas: &mut [u8],
+    canvas_w: u32,
+    thumbnail: &RgbaImage,
+    x: f32,
+    y: f32,
+    size: f32,
+    opacity: f32,
+) {
+    let canvas_h = canvas.len() as u32 / 4 / canvas_w.max(1);
+    let (x0, y0) = (x.round() as i32, y.round() as i32);
+    let size = size.round() as i32;
+    for dy in 0..size {
+        let dest_y = y0 + dy;
+        if dest_y < 0 || dest_y >= canvas_h as i32 {
+            continue;
+        }
+        let src_y = (dy as u32 * thumbnail.height() / size as u32).min(thumbnail.height() - 1);
+        for dx in 0..size {
+            let dest_x = x0 + dx;
+            if dest_x < 0 || dest_x >= canvas_w as i32 {
+                continue;
+            }
+            let src_x = (dx as u32 * thumbnail.width() / size as u32).min(thumbnail.width() - 1);
+            let [r, g, b, a] = thumbnail.get_pixel(src_x, src_y).0;
+            let index = ((dest_y as u32 * canvas_w + dest_x as u32) * 4) as usize;
+            blend_pixel(&mut canvas[index..index + 4], r, g, b, a, [1.0; 3], opacity);
+        }
+    }
+}
+
+/// Data URLs can be huge, so log messages only show their start.
+fn shorten(url: &str) -> &str {
+    match url.char_indices().nth(80) {
+        Some((index, _)) => &url[..index],
+        None => url,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_urls_get_their_own_cache_entry() {
+        let base = format!("https://example.com/{}", "a".repeat(300));
+        let first = art_cache_path(&format!("{base}/1.png")).unwrap();
+        let second = art_cache_path(&format!("{base}/2.png")).unwrap();
+        assert_ne!(first, second);
+        assert_eq!(first.file_name().unwrap().len(), 16);
+    }
+
+    #[test]
+    fn fnv1a_matches_reference_values() {
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+    }
+}
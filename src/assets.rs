@@ -0,0 +1,125 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// The default fonts and icon, built into the binary so a plain `cargo build`
+/// works from anywhere. Files in the asset directories take precedence.
+const EMBEDDED: [(&str, &[u8]); 7] = [
+    ("MusicTitleFont.fnt", include_bytes!("../assets/MusicTitleFont.fnt")),
+    ("MusicTitleFont.png", include_bytes!("../assets/MusicTitleFont.png")),
+    ("ShinonomeGothic.fnt", include_bytes!("../assets/ShinonomeGothic.fnt")),
+    ("ShinonomeGothic.png", include_bytes!("../assets/ShinonomeGothic.png")),
+    ("Ramche.fnt", include_bytes!("../assets/Ramche.fnt")),
+    ("Ramche.png", include_bytes!("../assets/Ramche.png")),
+    ("deltatune.png", include_bytes!("../assets/deltatune.png")),
+];
+
+/// Set by `--builtin-assets`.
+static BUILTIN_ONLY: AtomicBool = AtomicBool::new(false);
+
+/// Stops looking in the asset directories, so names only resolve to the
+/// built-in copies. Full paths still work. Renders then come out the same no
+/// matter what is installed, which the golden image tests rely on.
+pub(crate) fn use_builtin_assets_only() {
+    BUILTIN_ONLY.store(true, Ordering::Relaxed);
+}
+
+/// A font, atlas page or icon, either on disk or built in.
+pub(crate) enum Asset {
+    File(PathBuf),
+    Embedded { name: &'static str, data: &'static [u8] },
+}
+
+impl Asset {
+    pub(crate) fn read(&self) -> io::Result<Cow<'static, [u8]>> {
+        match self {
+            Self::File(path) => fs::read(path).map(Cow::Owned),
+            Self::Embedded { data, .. } => Ok(Cow::Borrowed(data)),
+        }
+    }
+
+    /// The file called `name` next to this one, like the atlas pages a font
+    /// refers to. Built-in assets only have built-in siblings.
+    pub(crate) fn sibling(&self, name: &str) -> Option<Asset> {
+        match self {
+            Self::File(path) => Some(Self::File(path.with_file_name(name))),
+            Self::Embedded { .. } => embedded(name),
+        }
+    }
+
+    pub(crate) fn file_stem(&self) -> Option<&str> {
+        match self {
+            Self::File(path) => path.file_stem().and_then(|stem| stem.to_str()),
+            Self::Embedded { name, .. } => name.rsplit_once('.').map(|(stem, _)| stem),
+        }
+    }
+}
+
+impl fmt::Display for Asset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::File(path) => write!(f, "{}", path.display()),
+            Self::Embedded { name, .. } => write!(f, "built-in {name}"),
+        }
+    }
+}
+
+/// Looks `name` up like `resolve_asset_path`, falling back to the built-in
+/// copy if there is one.
+pub(crate) fn resolve_asset(name: &str) -> Option<Asset> {
+    resolve_asset_path(name).map(Asset::File).or_else(|| embedded(name))
+}
+
+fn embedded(name: &str) -> Option<Asset> {
+    EMBEDDED
+        .iter()
+        .find(|(embedded_name, _)| *embedded_name == name)
+        .map(|&(name, data)| Asset::Embedded { name, data })
+}
+
+/// Finds an asset by file name in the first of `asset_dirs` that has it.
+/// Anything with a slash in it is taken as a path instead.
+fn resolve_asset_path(name: &str) -> Option<PathBuf> {
+    if name.contains('/') {
+        let path = PathBuf::from(name);
+        return path.exists().then_some(path);
+    }
+    asset_dirs()
+        .into_iter()
+        .map(|dir| dir.join(name))
+        .find(|path| path.exists())
+}
+
+/// Where fonts and other assets are looked up, most specific first:
+/// `$XDG_DATA_HOME/deltatune` (`~/.local/share/deltatune`), the `deltatune`
+/// directory in every `$XDG_DATA_DIRS` entry, then `./assets` for running
+/// from a checkout.
+fn asset_dirs() -> Vec<PathBuf> {
+    if BUILTIN_ONLY.load(Ordering::Relaxed) {
+        return Vec::new();
+    }
+    let data_home = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")));
+    let data_dirs = std::env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|dirs| !dirs.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".to_string());
+
+    let mut dirs: Vec<PathBuf> = data_home
+        .into_iter()
+        .chain(data_dirs.split(':').map(PathBuf::from).filter(|dir| dir.is_absolute()))
+        .map(|dir| dir.join("deltatune"))
+        .collect();
+    // The package installs here, even when XDG_DATA_DIRS leaves it out.
+    let installed = PathBuf::from("/usr/share/deltatune");
+    if !dirs.contains(&installed) {
+        dirs.push(installed);
+    }
+    dirs.push(PathBuf::from("assets"));
+    dirs
+}
@@ -0,0 +1,7 @@
+mod headless;
+mod wayland;
+mod x11;
+
+pub(crate) use headless::{run_headless, simulate, HeadlessOptions};
+pub(crate) use wayland::WaylandBackend;
+pub(crate) use x11::X11Backend;
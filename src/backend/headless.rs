@@ -0,0 +1,158 @@
+use crate::media::{parse_media_update, MediaInfo};
+use crate::overlay::{OverlayCore, PresentationBackend, Waker};
+use crate::bgra_to_straight_rgba;
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// How long to keep rendering after the last scripted event when no duration
+/// is given, which is enough for the default appear, stay and hide timings.
+const DEFAULT_TAIL: f64 = 5.0;
+
+pub(crate) struct HeadlessOptions {
+    /// Scripted media events, one `<seconds> <json>` pair per line.
+    pub(crate) script: Option<PathBuf>,
+    /// A directory for a frame sequence, or the PNG file for a single frame.
+    pub(crate) out: PathBuf,
+    /// Render only the frame at this many seconds.
+    pub(crate) at: Option<f64>,
+    pub(crate) fps: f64,
+    pub(crate) duration: Option<f64>,
+}
+
+/// Writes frames to PNG files instead of a display, so the overlay can be
+/// rendered in CI without a compositor.
+pub(crate) struct HeadlessBackend {
+    out: PathBuf,
+    single_frame: bool,
+    frame: u32,
+    canvas: Vec<u8>,
+}
+
+impl HeadlessBackend {
+    fn new(out: PathBuf, single_frame: bool) -> Result<Self> {
+        if !single_frame {
+            fs::create_dir_all(&out)
+                .with_context(|| format!("Failed to create frame directory {}", out.display()))?;
+        }
+        Ok(Self {
+            out,
+            single_frame,
+            frame: 0,
+            canvas: Vec::new(),
+        })
+    }
+}
+
+impl PresentationBackend for HeadlessBackend {
+    fn present(&mut self, core: &OverlayCore) -> Result<()> {
+        let (width, height) = core.canvas_size(1.0);
+        self.canvas.resize((width * height * 4) as usize, 0);
+        core.render(&mut self.canvas, width, height, 1.0);
+
+        let path = if self.single_frame {
+            self.out.clone()
+        } else {
+            self.out.join(format!("frame_{:05}.png", self.frame))
+        };
+        let image = image::RgbaImage::from_raw(width, height, bgra_to_straight_rgba(&self.canvas))
+            .ok_or_else(|| anyhow!("canvas does not match {width}x{height}"))?;
+        image
+            .save(&path)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        self.frame += 1;
+        Ok(())
+    }
+
+    // The simulated clock paces the headless run, so there is never anything
+    // to wait for.
+    fn wait_for_frame(&mut self) -> Result<bool> {
+        Ok(false)
+    }
+
+    fn wait(&mut self, _timeout: Duration) -> Result<bool> {
+        Ok(false)
+    }
+
+    fn waker(&self) -> Waker {
+        Box::new(|| {})
+    }
+}
+
+/// Steps `core` through the script on a simulated clock at `fps` and presents
+/// every frame, or only the one at `at`.
+pub(crate) fn run_headless(core: &mut OverlayCore, options: HeadlessOptions) -> Result<()> {
+    let events = match &options.script {
+        Some(path) => load_script(path)?,
+        None => Vec::new(),
+    };
+
+    let end = options
+        .at
+        .or(options.duration)
+        .unwrap_or_else(|| events.last().map_or(0.0, |(time, _)| *time) + DEFAULT_TAIL);
+    let mut backend = HeadlessBackend::new(options.out, options.at.is_some())?;
+
+    simulate(core, &events, options.fps, end, |core, time| {
+        if options.at.is_none() || time >= end {
+            backend.present(core)?;
+        }
+        Ok(true)
+    })
+}
+
+/// Feeds `events` to `core` on a simulated clock, calling `on_frame` after
+/// every tick until `end` seconds or until it returns false. The last frame is
+/// always exactly at `end`.
+pub(crate) fn simulate(
+    core: &mut OverlayCore,
+    events: &[(f64, MediaInfo)],
+    fps: f64,
+    end: f64,
+    mut on_frame: impl FnMut(&OverlayCore, f64) -> Result<bool>,
+) -> Result<()> {
+    if fps <= 0.0 {
+        return Err(anyhow!("--fps must be positive"));
+    }
+
+    let start = Instant::now();
+    let frame_interval = 1.0 / fps;
+    let mut next_event = 0;
+    let mut frame = 0u32;
+    loop {
+        let time = (frame as f64 * frame_interval).min(end);
+        while let Some((_, info)) = events.get(next_event).filter(|(at, _)| *at <= time) {
+            core.set_media(info.clone());
+            next_event += 1;
+        }
+        core.tick(start + Duration::from_secs_f64(time));
+
+        if !on_frame(core, time)? || time >= end {
+            return Ok(());
+        }
+        frame += 1;
+    }
+}
+
+fn load_script(path: &Path) -> Result<Vec<(f64, MediaInfo)>> {
+    let data = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut events = Vec::new();
+    for (index, line) in data.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (time, json) = line
+            .split_once(char::is_whitespace)
+            .ok_or_else(|| anyhow!("{}:{}: expected `<seconds> <json>`", path.display(), index + 1))?;
+        let time: f64 = time
+            .parse()
+            .map_err(|err| anyhow!("{}:{}: bad time {time:?}: {err}", path.display(), index + 1))?;
+        let info = parse_media_update(json)
+            .map_err(|err| anyhow!("{}:{}: {err}", path.display(), index + 1))?;
+        events.push((time, info));
+    }
+    events.sort_by(|a, b| a.0.total_cmp(&b.0));
+    Ok(events)
+}
@@ -0,0 +1,590 @@
+use crate::overlay::{OverlayCore, PresentationBackend, Waker};
+use crate::{layer_margins, OutputSelection, Settings};
+use anyhow::{anyhow, Result};
+use smithay_client_toolkit::reexports::calloop::{
+    ping::{make_ping, Ping},
+    EventLoop,
+};
+use smithay_client_toolkit::reexports::calloop_wayland_source::WaylandSource;
+use smithay_client_toolkit::reexports::protocols::wp::{
+    fractional_scale::v1::client::{
+        wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1,
+        wp_fractional_scale_v1::{self, WpFractionalScaleV1},
+    },
+    viewporter::client::{wp_viewport::WpViewport, wp_viewporter::WpViewporter},
+};
+use smithay_client_toolkit::{
+    compositor::{CompositorHandler, CompositorState},
+    delegate_compositor, delegate_layer, delegate_output, delegate_registry, delegate_shm,
+    output::{OutputHandler, OutputState},
+    registry::{ProvidesRegistryState, RegistryState},
+    registry_handlers,
+    shell::wlr_layer::{
+        KeyboardInteractivity, Layer, LayerShell, LayerShellHandler, LayerSurface, LayerSurfaceConfigure,
+    },
+    shell::WaylandSurface,
+    shm::{slot::SlotPool, Shm, ShmHandler},
+};
+use std::time::{Duration, Instant};
+use wayland_client::{
+    globals::registry_queue_init,
+    protocol::{wl_output, wl_shm, wl_surface},
+    Connection, Dispatch, QueueHandle,
+};
+
+/// Presents the overlay as wlr-layer-shell surfaces, one per selected output.
+pub(crate) struct WaylandBackend {
+    event_loop: EventLoop<'static, WaylandState>,
+    state: WaylandState,
+    ping: Ping,
+}
+
+impl WaylandBackend {
+    pub(crate) fn new(settings: &Settings) -> Result<Self> {
+        let conn = Connection::connect_to_env()?;
+        let (globals, event_queue) = registry_queue_init(&conn)?;
+        let qh = event_queue.handle();
+        let event_loop: EventLoop<WaylandState> = EventLoop::try_new()?;
+
+        let compositor = CompositorState::bind(&globals, &qh).expect("wl_compositor unavailable");
+        let layer_shell = LayerShell::bind(&globals, &qh).expect("layer-shell unavailable");
+        let shm = Shm::bind(&globals, &qh).expect("wl_shm unavailable");
+        let fractional_scale_manager = globals.bind::<WpFractionalScaleManagerV1, _, _>(&qh, 1..=1, ()).ok();
+        let viewporter = globals.bind::<WpViewporter, _, _>(&qh, 1..=1, ()).ok();
+        let pool = SlotPool::new(4, &shm).expect("Failed to create slot pool");
+
+        let mut state = WaylandState {
+            queue_handle: qh.clone(),
+            registry_state: RegistryState::new(&globals),
+            output_state: OutputState::new(&globals, &qh),
+            compositor,
+            layer_shell,
+            shm,
+            fractional_scale_manager,
+            viewporter,
+            surfaces: Vec::new(),
+            pool,
+            settings: settings.clone(),
+            output_warned: false,
+            closing_outputs: Vec::new(),
+            needs_redraw: false,
+            frame_done: false,
+            woken: false,
+        };
+        state.sync_surfaces();
+
+        WaylandSource::new(conn, event_queue)
+            .insert(event_loop.handle())
+            .map_err(|err| anyhow!("Failed to watch the Wayland connection: {}", err.error))?;
+
+        let (ping, ping_source) = make_ping()?;
+        event_loop
+            .handle()
+            .insert_source(ping_source, |_, _, state| state.woken = true)
+            .map_err(|err| anyhow!("Failed to watch for wakeups: {}", err.error))?;
+
+        Ok(Self {
+            event_loop,
+            state,
+            ping,
+        })
+    }
+
+    fn dispatch(&mut self, timeout: Option<Duration>) -> Result<()> {
+        self.event_loop.dispatch(timeout, &mut self.state)?;
+        Ok(())
+    }
+
+    fn take_redraw(&mut self) -> bool {
+        self.state.woken = false;
+        self.state.frame_done = false;
+        std::mem::take(&mut self.state.needs_redraw)
+    }
+}
+
+impl PresentationBackend for WaylandBackend {
+    fn present(&mut self, core: &OverlayCore) -> Result<()> {
+        if core.settings != self.state.settings {
+            let output_changed = core.settings.output != self.state.settings.output;
+            self.state.settings = core.settings.clone();
+            if output_changed {
+                self.state.output_warned = false;
+                self.state.sync_surfaces();
+            }
+        }
+
+        let qh = self.state.queue_handle.clone();
+        for overlay in self.state.surfaces.iter_mut().filter(|overlay| overlay.configured) {
+            overlay.draw(&qh, &mut self.state.pool, core);
+        }
+        Ok(())
+    }
+
+    fn wait_for_frame(&mut self) -> Result<bool> {
+        if !self.state.surfaces.iter().any(|overlay| overlay.frame_requested) {
+            // Nothing is on screen to pace the animation, so fall back to 60 Hz.
+            self.dispatch(Some(Duration::from_micros(16_666)))?;
+            return Ok(self.take_redraw());
+        }
+
+        while !self.state.frame_done && !self.state.needs_redraw && !self.state.woken {
+            self.dispatch(None)?;
+        }
+        Ok(self.take_redraw())
+    }
+
+    fn wait(&mut self, timeout: Duration) -> Result<bool> {
+        let deadline = Instant::now() + timeout;
+        while !self.state.needs_redraw && !self.state.woken {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            self.dispatch(Some(remaining))?;
+        }
+        Ok(self.take_redraw())
+    }
+
+    fn waker(&self) -> Waker {
+        let ping = self.ping.clone();
+        Box::new(move || ping.ping())
+    }
+}
+
+struct OverlaySurface {
+    layer: LayerSurface,
+    output: Option<wl_output::WlOutput>,
+    width: u32,
+    height: u32,
+    configured: bool,
+    frame_requested: bool,
+    /// Device pixels per logical pixel, from `wp_fractional_scale_v1` when the
+    /// compositor supports it and the integer buffer scale otherwise.
+    buffer_scale: f64,
+    fractional_scale: Option<WpFractionalScaleV1>,
+    viewport: Option<WpViewport>,
+}
+
+impl OverlaySurface {
+    fn draw(&mut self, qh: &QueueHandle<WaylandState>, pool: &mut SlotPool, core: &OverlayCore) {
+        let buffer_scale = if core.settings.hidpi_scaling { self.buffer_scale } else { 1.0 };
+
+        // The core measures in device pixels, but the layer surface is sized in
+        // logical pixels.
+        let (content_width, content_height) = core.canvas_size(buffer_scale);
+        let desired_width = (content_width as f64 / buffer_scale).ceil().max(1.0) as u32;
+        let desired_height = (content_height as f64 / buffer_scale).ceil().max(1.0) as u32;
+
+        if desired_width != self.width || desired_height != self.height {
+            self.width = desired_width;
+            self.height = desired_height;
+            self.layer.set_size(self.width, self.height);
+        }
+
+        let (buffer_width, buffer_height) = match &self.viewport {
+            Some(viewport) => {
+                viewport.set_destination(self.width as i32, self.height as i32);
+                (
+                    (self.width as f64 * buffer_scale).round().max(1.0) as u32,
+                    (self.height as f64 * buffer_scale).round().max(1.0) as u32,
+                )
+            }
+            None => {
+                let integer_scale = buffer_scale.round().max(1.0) as u32;
+                self.layer.wl_surface().set_buffer_scale(integer_scale as i32);
+                (self.width * integer_scale, self.height * integer_scale)
+            }
+        };
+
+        let stride = buffer_width as i32 * 4;
+        let (buffer, canvas) = pool
+            .create_buffer(buffer_width as i32, buffer_height as i32, stride, wl_shm::Format::Argb8888)
+            .expect("create buffer");
+        core.render(canvas, buffer_width, buffer_height, buffer_scale);
+
+        self.layer
+            .wl_surface()
+            .damage_buffer(0, 0, buffer_width as i32, buffer_height as i32);
+        if core.is_animating() {
+            self.layer.wl_surface().frame(qh, self.layer.wl_surface().clone());
+            self.frame_requested = true;
+        }
+        self.layer.set_anchor(core.settings.text_anchor.layer_anchor());
+        let (mut top, right, mut bottom, left) = layer_margins(&core.settings);
+        // The canvas grows around text that slides up or down. Pull the
+        // anchored edge out by as much so the resting text stays in place.
+        let (room_above, room_below) = core.vertical_slide_room(buffer_scale);
+        if core.settings.text_anchor.is_bottom() {
+            bottom -= (room_below as f64 / buffer_scale).round() as i32;
+        } else {
+            top -= (room_above as f64 / buffer_scale).round() as i32;
+        }
+        self.layer.set_margin(top, right, bottom, left);
+        buffer.attach_to(self.layer.wl_surface()).expect("buffer attach");
+        self.layer.commit();
+    }
+}
+
+impl Drop for OverlaySurface {
+    fn drop(&mut self) {
+        if let Some(fractional_scale) = self.fractional_scale.take() {
+            fractional_scale.destroy();
+        }
+        if let Some(viewport) = self.viewport.take() {
+            viewport.destroy();
+        }
+    }
+}
+
+struct WaylandState {
+    queue_handle: QueueHandle<WaylandState>,
+    registry_state: RegistryState,
+    output_state: OutputState,
+    compositor: CompositorState,
+    layer_shell: LayerShell,
+    shm: Shm,
+    fractional_scale_manager: Option<WpFractionalScaleManagerV1>,
+    viewporter: Option<WpViewporter>,
+    surfaces: Vec<OverlaySurface>,
+    pool: SlotPool,
+    /// The settings of the last presented frame.
+    settings: Settings,
+    /// Whether the missing output in `settings.output` was already reported.
+    output_warned: bool,
+    /// Outputs that went away, or whose surface the compositor closed, but
+    /// that OutputState may still list.
+    closing_outputs: Vec<wl_output::WlOutput>,
+    needs_redraw: bool,
+    frame_done: bool,
+    woken: bool,
+}
+
+impl WaylandState {
+    fn live_outputs(&self) -> impl Iterator<Item = wl_output::WlOutput> + '_ {
+        self.output_state
+            .outputs()
+            .filter(|output| !self.closing_outputs.contains(output))
+    }
+
+    fn wanted_outputs(&self) -> Vec<Option<wl_output::WlOutput>> {
+        let matches = |output: &wl_output::WlOutput| {
+            let Some(info) = self.output_state.info(output) else {
+                return false;
+            };
+            match &self.settings.output {
+                OutputSelection::Focused | OutputSelection::Primary => false,
+                OutputSelection::All => true,
+                OutputSelection::Name(name) => info.name.as_deref() == Some(name.as_str()),
+                OutputSelection::Description(text) => info
+                    .description
+                    .as_deref()
+                    .is_some_and(|description| description.contains(text.as_str())),
+            }
+        };
+
+        match self.settings.output {
+            OutputSelection::Focused | OutputSelection::Primary => vec![None],
+            OutputSelection::All => self.live_outputs().filter(matches).map(Some).collect(),
+            OutputSelection::Name(_) | OutputSelection::Description(_) => {
+                vec![self.live_outputs().find(matches)]
+            }
+        }
+    }
+
+    /// Reports once when a named output is missing and the overlay falls back
+    /// to the compositor's choice. Outputs whose info has not arrived yet, as
+    /// during startup, are not treated as missing.
+    fn warn_missing_output(&mut self, wanted: &[Option<wl_output::WlOutput>]) {
+        let named = matches!(
+            self.settings.output,
+            OutputSelection::Name(_) | OutputSelection::Description(_)
+        );
+        if !named || wanted != [None] {
+            self.output_warned = false;
+            return;
+        }
+        let outputs_known = self
+            .live_outputs()
+            .all(|output| self.output_state.info(&output).is_some());
+        if outputs_known && !self.output_warned {
+            eprintln!(
+                "No Wayland output matches {:?}, using the focused output",
+                self.settings.output
+            );
+            self.output_warned = true;
+        }
+    }
+
+    /// Creates and destroys layer surfaces until there is exactly one per wanted output.
+    fn sync_surfaces(&mut self) {
+        let qh = self.queue_handle.clone();
+        let listed: Vec<_> = self.output_state.outputs().collect();
+        self.closing_outputs.retain(|output| listed.contains(output));
+        let wanted = self.wanted_outputs();
+        self.warn_missing_output(&wanted);
+        self.surfaces.retain(|surface| wanted.contains(&surface.output));
+
+        for output in wanted {
+            if self.surfaces.iter().any(|surface| surface.output == output) {
+                continue;
+            }
+
+            let surface = self.compositor.create_surface(&qh);
+            // Fractional scales are only usable together with a viewport, which
+            // maps the device-sized buffer back onto the logical surface size.
+            let (fractional_scale, viewport) = match (&self.fractional_scale_manager, &self.viewporter) {
+                (Some(manager), Some(viewporter)) => (
+                    Some(manager.get_fractional_scale(&surface, &qh, surface.clone())),
+                    Some(viewporter.get_viewport(&surface, &qh, ())),
+                ),
+                _ => (None, None),
+            };
+            let layer = self.layer_shell.create_layer_surface(
+                &qh,
+                surface,
+                Layer::Overlay,
+                Some("deltatune"),
+                output.as_ref(),
+            );
+            layer.set_anchor(self.settings.text_anchor.layer_anchor());
+            let (top, right, bottom, left) = layer_margins(&self.settings);
+            layer.set_margin(top, right, bottom, left);
+            layer.set_keyboard_interactivity(KeyboardInteractivity::None);
+            layer.set_exclusive_zone(-1);
+            layer.set_size(1, 1);
+            layer.commit();
+
+            self.surfaces.push(OverlaySurface {
+                layer,
+                output,
+                width: 1,
+                height: 1,
+                configured: false,
+                frame_requested: false,
+                buffer_scale: 1.0,
+                fractional_scale,
+                viewport,
+            });
+        }
+    }
+
+    fn surface_mut(&mut self, surface: &wl_surface::WlSurface) -> Option<&mut OverlaySurface> {
+        self.surfaces
+            .iter_mut()
+            .find(|overlay| overlay.layer.wl_surface() == surface)
+    }
+}
+
+impl CompositorHandler for WaylandState {
+    fn scale_factor_changed(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        surface: &wl_surface::WlSurface,
+        new_factor: i32,
+    ) {
+        let Some(overlay) = self.surface_mut(surface) else {
+            return;
+        };
+        // The fractional scale is more precise, so it wins once it is available.
+        if overlay.fractional_scale.is_none() {
+            overlay.buffer_scale = new_factor.max(1) as f64;
+            self.needs_redraw = true;
+        }
+    }
+
+    fn transform_changed(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _surface: &wl_surface::WlSurface,
+        _new_transform: wl_output::Transform,
+    ) {
+    }
+
+    fn frame(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        surface: &wl_surface::WlSurface,
+        _time: u32,
+    ) {
+        if let Some(overlay) = self.surface_mut(surface) {
+            overlay.frame_requested = false;
+        }
+        self.frame_done = true;
+    }
+
+    fn surface_enter(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _surface: &wl_surface::WlSurface,
+        _output: &wl_output::WlOutput,
+    ) {
+    }
+
+    fn surface_leave(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _surface: &wl_surface::WlSurface,
+        _output: &wl_output::WlOutput,
+    ) {
+    }
+}
+
+impl OutputHandler for WaylandState {
+    fn output_state(&mut self) -> &mut OutputState {
+        &mut self.output_state
+    }
+
+    fn new_output(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _output: wl_output::WlOutput) {
+        self.sync_surfaces();
+    }
+    fn update_output(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        output: wl_output::WlOutput,
+    ) {
+        // An output that changes is evidently still there.
+        self.closing_outputs.retain(|closing| closing != &output);
+        self.sync_surfaces();
+    }
+    fn output_destroyed(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        output: wl_output::WlOutput,
+    ) {
+        // The output is still listed in OutputState at this point, so leave it
+        // out explicitly. Without it a named output falls back to the
+        // compositor's choice.
+        self.surfaces
+            .retain(|surface| surface.output.as_ref() != Some(&output));
+        self.closing_outputs.push(output);
+        self.sync_surfaces();
+    }
+}
+
+impl ShmHandler for WaylandState {
+    fn shm_state(&mut self) -> &mut Shm {
+        &mut self.shm
+    }
+}
+
+impl LayerShellHandler for WaylandState {
+    fn closed(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, layer: &LayerSurface) {
+        // The compositor closes surfaces whose output is going away, which
+        // may still be listed in OutputState. Put the overlay back on another
+        // monitor as long as there is one left to pick.
+        let closed_output = self
+            .surfaces
+            .iter()
+            .find(|surface| surface.layer.wl_surface() == layer.wl_surface())
+            .and_then(|surface| surface.output.clone());
+        self.surfaces
+            .retain(|surface| surface.layer.wl_surface() != layer.wl_surface());
+        self.closing_outputs.extend(closed_output);
+        if self.live_outputs().next().is_some() {
+            self.sync_surfaces();
+        }
+    }
+
+    fn configure(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        layer: &LayerSurface,
+        configure: LayerSurfaceConfigure,
+        _serial: u32,
+    ) {
+        let Some(overlay) = self.surface_mut(layer.wl_surface()) else {
+            return;
+        };
+
+        if configure.new_size.0 != 0 && configure.new_size.1 != 0 {
+            overlay.width = configure.new_size.0;
+            overlay.height = configure.new_size.1;
+        }
+
+        if !overlay.configured {
+            overlay.configured = true;
+            self.needs_redraw = true;
+        }
+    }
+}
+
+impl ProvidesRegistryState for WaylandState {
+    fn registry(&mut self) -> &mut RegistryState {
+        &mut self.registry_state
+    }
+    registry_handlers![OutputState];
+}
+
+delegate_compositor!(WaylandState);
+delegate_output!(WaylandState);
+
+delegate_shm!(WaylandState);
+
+delegate_layer!(WaylandState);
+
+delegate_registry!(WaylandState);
+
+impl Dispatch<WpFractionalScaleV1, wl_surface::WlSurface> for WaylandState {
+    fn event(
+        state: &mut Self,
+        _proxy: &WpFractionalScaleV1,
+        event: wp_fractional_scale_v1::Event,
+        surface: &wl_surface::WlSurface,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let wp_fractional_scale_v1::Event::PreferredScale { scale } = event else {
+            return;
+        };
+        if let Some(overlay) = state.surface_mut(surface) {
+            // The protocol sends the scale as a multiple of 1/120.
+            overlay.buffer_scale = scale as f64 / 120.0;
+            state.needs_redraw = true;
+        }
+    }
+}
+
+// The remaining scaling objects have no events.
+impl Dispatch<WpFractionalScaleManagerV1, ()> for WaylandState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WpFractionalScaleManagerV1,
+        _event: <WpFractionalScaleManagerV1 as wayland_client::Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<WpViewporter, ()> for WaylandState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WpViewporter,
+        _event: <WpViewporter as wayland_client::Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<WpViewport, ()> for WaylandState {
+    fn event(
+        _state: &mut Self,
+        _proxy: &WpViewport,
+        _event: <WpViewport as wayland_client::Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+}
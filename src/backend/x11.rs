@@ -0,0 +1,344 @@
+use crate::overlay::{content_margin, panel_inset, OverlayCore, PresentationBackend, Waker, PADDING};
+use crate::{
+    load_icon_buffer, pack_bgra_to_argb, BitmapFont, OutputSelection, OverflowMode, Settings, SlideDirection,
+};
+use anyhow::Result;
+use minifb::{Icon as MinifbIcon, Window, WindowOptions};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::Duration;
+
+/// Presents the overlay in a borderless, click-through minifb window.
+pub(crate) struct X11Backend {
+    window: Window,
+    monitor: X11Monitor,
+    monitor_selection: OutputSelection,
+    width: u32,
+    height: u32,
+    canvas: Vec<u8>,
+    pixels: Vec<u32>,
+    wake_tx: Sender<()>,
+    wake_rx: Receiver<()>,
+}
+
+impl X11Backend {
+    pub(crate) fn new(core: &OverlayCore) -> Result<Self> {
+        let monitor = select_x11_monitor(&core.settings.output);
+        let (width, height) = compute_x11_window_size(&core.settings, &core.font, &monitor);
+        let mut window = create_x11_window(width, height)?;
+        let (window_x, window_y) = x11_window_position(&core.settings, &monitor, width, height, 0.0);
+        window.set_position(window_x, window_y);
+
+        let (wake_tx, wake_rx) = mpsc::channel();
+        Ok(Self {
+            window,
+            monitor,
+            monitor_selection: core.settings.output.clone(),
+            width,
+            height,
+            canvas: Vec::new(),
+            pixels: Vec::new(),
+            wake_tx,
+            wake_rx,
+        })
+    }
+}
+
+impl PresentationBackend for X11Backend {
+    fn present(&mut self, core: &OverlayCore) -> Result<()> {
+        if core.settings.output != self.monitor_selection {
+            self.monitor_selection = core.settings.output.clone();
+            self.monitor = select_x11_monitor(&self.monitor_selection);
+        }
+
+        // The window is sized for its monitor and the settings, so a monitor
+        // of a different width or new slide distances need a new window.
+        let (width, height) = compute_x11_window_size(&core.settings, &core.font, &self.monitor);
+        if (width, height) != (self.width, self.height) {
+            self.window = create_x11_window(width, height)?;
+            self.width = width;
+            self.height = height;
+        }
+
+        let needed = (self.width * self.height * 4) as usize;
+        if self.canvas.len() != needed {
+            self.canvas.resize(needed, 0);
+        }
+        core.render(&mut self.canvas, self.width, self.height, 1.0);
+        pack_bgra_to_argb(&self.canvas, &mut self.pixels);
+
+        // The canvas grows around text that slides up or down. Move the window
+        // up by as much so the resting text stays in place.
+        let (room_above, _) = core.vertical_slide_room(1.0);
+        let (window_x, window_y) =
+            x11_window_position(&core.settings, &self.monitor, self.width, self.height, room_above);
+        self.window.set_position(window_x, window_y);
+        self.window
+            .update_with_buffer(&self.pixels, self.width as usize, self.height as usize)?;
+        Ok(())
+    }
+
+    fn wait_for_frame(&mut self) -> Result<bool> {
+        // update_with_buffer already limits presenting to 60 Hz.
+        Ok(false)
+    }
+
+    fn wait(&mut self, timeout: Duration) -> Result<bool> {
+        if self.wake_rx.recv_timeout(timeout).is_ok() {
+            while self.wake_rx.try_recv().is_ok() {}
+        }
+        // Keep the window responsive while nothing is presented.
+        self.window.update();
+        Ok(false)
+    }
+
+    fn waker(&self) -> Waker {
+        let wake_tx = self.wake_tx.clone();
+        Box::new(move || {
+            let _ = wake_tx.send(());
+        })
+    }
+
+    fn is_open(&self) -> bool {
+        self.window.is_open()
+    }
+}
+
+fn create_x11_window(width: u32, height: u32) -> Result<Window> {
+    let window_options = WindowOptions {
+        borderless: true,
+        title: false,
+        resize: false,
+        topmost: true,
+        transparency: true,
+        ..WindowOptions::default()
+    };
+    let mut window = Window::new("DeltaTune", width as usize, height as usize, window_options)?;
+    if let Some(data) = load_icon_buffer() {
+        if let Ok(icon) = MinifbIcon::try_from(data.as_slice()) {
+            window.set_icon(icon);
+        }
+    }
+    apply_x11_overlay_hints(&window);
+    window.limit_update_rate(Some(Duration::from_micros(16_666)));
+    Ok(window)
+}
+
+/// minifb cannot resize a window after creating it, so the window spans the
+/// whole monitor width, or `max_width` if that is narrower, and is tall enough
+/// for the title and artist lines, the album art and text sliding up or down.
+fn compute_x11_window_size(settings: &Settings, font: &BitmapFont, monitor: &X11Monitor) -> (u32, u32) {
+    let screen_w = settings.max_width.map_or(monitor.width, |max_width| max_width.min(monitor.width));
+    let scale = settings.scale_factor * settings.text_scale;
+    let padding = PADDING;
+    // Wrapped text can take up to its line limit.
+    let (title_lines, artist_lines) = if settings.overflow == OverflowMode::Wrap {
+        (settings.title_max_lines.max(1), settings.artist_max_lines.max(1))
+    } else {
+        (1, 1)
+    };
+    let mut lines = settings.title_style.scale * title_lines as f32;
+    if settings.show_artist_name {
+        lines += settings.artist_style.scale * artist_lines as f32;
+    }
+    let margin = content_margin(settings, scale, false);
+    let mut content_height = font.line_height * scale * lines + margin.top + margin.bottom;
+    if let Some(art) = &settings.album_art {
+        content_height = content_height.max(art.size as f32 * scale + panel_inset(settings, scale) * 2.0);
+    }
+    let height = ((content_height + x11_slide_room(settings) + padding * 2.0) * settings.scale_y)
+        .max(1.0)
+        .round() as u32;
+    (screen_w.max(1), height.max(1))
+}
+
+/// The most text sliding up or down can reach past its resting place, in
+/// pixels. An appearing and a disappearing text can slide at the same time.
+fn x11_slide_room(settings: &Settings) -> f32 {
+    let animation = &settings.animation;
+    let scale = settings.scale_factor * settings.text_scale;
+    let is_vertical = |direction| matches!(direction, SlideDirection::Up | SlideDirection::Down);
+    let mut room = 0.0;
+    if is_vertical(animation.appear_direction) {
+        room += animation.slide_in_distance.abs() * scale;
+    }
+    if is_vertical(animation.disappear_direction) {
+        room += animation.slide_out_distance.abs() * scale;
+    }
+    room.ceil()
+}
+
+/// Places the window so that `x_pos`/`y_pos` are the distance from the screen
+/// edges the resting text is anchored to. `room_above` is how far the text
+/// currently slides above its resting place.
+fn x11_window_position(
+    settings: &Settings,
+    monitor: &X11Monitor,
+    window_w: u32,
+    window_h: u32,
+    room_above: f32,
+) -> (isize, isize) {
+    let x = if settings.text_anchor.is_right() {
+        monitor.width as isize - window_w as isize - settings.x_pos as isize
+    } else {
+        settings.x_pos as isize
+    };
+    // The slide room at the bottom of the window is empty while the text rests.
+    let slide_room = (x11_slide_room(settings) * settings.scale_y).round() as isize;
+    let resting_h = (window_h as isize - slide_room).max(1);
+    let y = if settings.text_anchor.is_bottom() {
+        monitor.height as isize - resting_h - settings.y_pos as isize
+    } else {
+        settings.y_pos as isize
+    };
+    let y = y - room_above.round() as isize;
+    (monitor.x as isize + x, monitor.y as isize + y)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct X11Monitor {
+    name: String,
+    primary: bool,
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+}
+
+/// Picks the monitor the X11 window lives on. There is only ever one window, so
+/// `focused` and `all` fall back to the primary monitor, and descriptions are
+/// matched against the RandR monitor name.
+fn select_x11_monitor(selection: &OutputSelection) -> X11Monitor {
+    let monitors = x11_monitors();
+    let primary = || {
+        monitors
+            .iter()
+            .find(|monitor| monitor.primary)
+            .or_else(|| monitors.first())
+            .cloned()
+    };
+
+    let selected = match selection {
+        OutputSelection::Name(name) => monitors.iter().find(|monitor| &monitor.name == name).cloned(),
+        OutputSelection::Description(text) => monitors
+            .iter()
+            .find(|monitor| monitor.name.contains(text.as_str()))
+            .cloned(),
+        OutputSelection::Focused | OutputSelection::All | OutputSelection::Primary => None,
+    };
+    if selected.is_none() && matches!(selection, OutputSelection::Name(_) | OutputSelection::Description(_)) {
+        eprintln!("No X11 monitor matches {selection:?}, using the primary monitor");
+    }
+
+    selected.or_else(primary).unwrap_or_else(|| {
+        let (width, height) = x11_screen_size().unwrap_or((800, 600));
+        X11Monitor {
+            name: String::new(),
+            primary: true,
+            x: 0,
+            y: 0,
+            width,
+            height,
+        }
+    })
+}
+
+fn x11_monitors() -> Vec<X11Monitor> {
+    #[cfg(target_os = "linux")]
+    unsafe {
+        let Ok(xlib) = x11_dl::xlib::Xlib::open() else {
+            return Vec::new();
+        };
+        let Ok(xrandr) = x11_dl::xrandr::Xrandr::open() else {
+            return Vec::new();
+        };
+        let display = (xlib.XOpenDisplay)(std::ptr::null());
+        if display.is_null() {
+            return Vec::new();
+        }
+
+        let root = (xlib.XDefaultRootWindow)(display);
+        let mut count = 0;
+        let infos = (xrandr.XRRGetMonitors)(display, root, 1, &mut count);
+        let mut monitors = Vec::new();
+        if !infos.is_null() {
+            for info in std::slice::from_raw_parts(infos, count.max(0) as usize) {
+                let name_ptr = (xlib.XGetAtomName)(display, info.name);
+                let name = if name_ptr.is_null() {
+                    String::new()
+                } else {
+                    let name = std::ffi::CStr::from_ptr(name_ptr).to_string_lossy().into_owned();
+                    (xlib.XFree)(name_ptr.cast());
+                    name
+                };
+                monitors.push(X11Monitor {
+                    name,
+                    primary: info.primary != 0,
+                    x: info.x,
+                    y: info.y,
+                    width: info.width.max(1) as u32,
+                    height: info.height.max(1) as u32,
+                });
+            }
+            (xrandr.XRRFreeMonitors)(infos);
+        }
+
+        (xlib.XCloseDisplay)(display);
+        monitors
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        Vec::new()
+    }
+}
+
+fn x11_screen_size() -> Option<(u32, u32)> {
+    #[cfg(target_os = "linux")]
+    unsafe {
+        let xlib = x11_dl::xlib::Xlib::open().ok()?;
+        let display = (xlib.XOpenDisplay)(std::ptr::null());
+        if display.is_null() {
+            return None;
+        }
+        let screen = (xlib.XDefaultScreen)(display);
+        let width = (xlib.XDisplayWidth)(display, screen).max(1) as u32;
+        let height = (xlib.XDisplayHeight)(display, screen).max(1) as u32;
+        (xlib.XCloseDisplay)(display);
+        Some((width, height))
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        None
+    }
+}
+
+fn apply_x11_overlay_hints(window: &Window) {
+    #[cfg(target_os = "linux")]
+    unsafe {
+        let xlib = match x11_dl::xlib::Xlib::open() {
+            Ok(lib) => lib,
+            Err(_) => return,
+        };
+        let xfixes = match x11_dl::xfixes::Xlib::open() {
+            Ok(lib) => lib,
+            Err(_) => return,
+        };
+        let display = (xlib.XOpenDisplay)(std::ptr::null());
+        if display.is_null() {
+            return;
+        }
+        let window_id = window.get_window_handle() as x11_dl::xlib::Window;
+
+        let mut hints: x11_dl::xlib::XWMHints = std::mem::zeroed();
+        hints.flags = x11_dl::xlib::InputHint;
+        hints.input = 0;
+        (xlib.XSetWMHints)(display, window_id, &mut hints);
+
+        let region = (xfixes.XFixesCreateRegion)(display, std::ptr::null_mut(), 0);
+        const SHAPE_INPUT: i32 = 2;
+        (xfixes.XFixesSetWindowShapeRegion)(display, window_id, SHAPE_INPUT, 0, 0, region);
+        (xfixes.XFixesDestroyRegion)(display, region);
+
+        (xlib.XFlush)(display);
+        (xlib.XCloseDisplay)(display);
+    }
+}
@@ -0,0 +1,609 @@
+use crate::assets::Asset;
+use crate::{BitmapFont, Glyph};
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Why a BMFont descriptor could not be loaded. Line numbers are 1-based and
+/// refer to the text or XML file.
+#[derive(Debug)]
+pub(crate) enum FontError {
+    Io(std::io::Error),
+    NotUtf8,
+    Syntax { line: usize, message: String },
+    MissingField { line: usize, tag: String, field: &'static str },
+    InvalidValue { line: usize, field: &'static str, value: String },
+    MissingCommon,
+    UnsupportedBinaryVersion(u8),
+    TruncatedBlock { block: u8 },
+}
+
+impl fmt::Display for FontError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{err}"),
+            Self::NotUtf8 => write!(f, "not a binary font and not valid UTF-8 text"),
+            Self::Syntax { line, message } => write!(f, "line {line}: {message}"),
+            Self::MissingField { line, tag, field } => write!(f, "line {line}: `{tag}` is missing `{field}`"),
+            Self::InvalidValue { line, field, value } => {
+                write!(f, "line {line}: invalid value {value:?} for `{field}`")
+            }
+            Self::MissingCommon => write!(f, "no `common` block with the line height and texture size"),
+            Self::UnsupportedBinaryVersion(version) => {
+                write!(f, "binary font version {version} is not supported, only version 3 is")
+            }
+            Self::TruncatedBlock { block } => write!(f, "binary block {block} ends early"),
+        }
+    }
+}
+
+impl std::error::Error for FontError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for FontError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+/// Loads an AngelCode BMFont descriptor, detecting whether it was exported as
+/// text, XML or binary.
+pub(crate) fn load_bitmap_font(asset: &Asset) -> Result<BitmapFont, FontError> {
+    parse_bitmap_font(&asset.read()?)
+}
+
+fn parse_bitmap_font(data: &[u8]) -> Result<BitmapFont, FontError> {
+    if let Some(data) = data.strip_prefix(b"BMF") {
+        return parse_binary(data);
+    }
+
+    let text = std::str::from_utf8(data).map_err(|_| FontError::NotUtf8)?;
+    let text = text.trim_start_matches('\u{feff}');
+    let tags = if text.trim_start().starts_with('<') {
+        parse_xml(text)?
+    } else {
+        parse_text(text)?
+    };
+
+    let mut builder = FontBuilder::default();
+    for tag in &tags {
+        builder.add_tag(tag)?;
+    }
+    builder.finish()
+}
+
+/// One `common`, `page`, `char`, ... entry of a text or XML descriptor.
+struct Tag {
+    line: usize,
+    name: String,
+    attributes: HashMap<String, String>,
+}
+
+impl Tag {
+    fn get<T: FromStr>(&self, field: &'static str) -> Result<T, FontError> {
+        match self.attributes.get(field) {
+            Some(value) => self.parse(field, value),
+            None => Err(FontError::MissingField {
+                line: self.line,
+                tag: self.name.clone(),
+                field,
+            }),
+        }
+    }
+
+    fn get_or<T: FromStr>(&self, field: &'static str, default: T) -> Result<T, FontError> {
+        match self.attributes.get(field) {
+            Some(value) => self.parse(field, value),
+            None => Ok(default),
+        }
+    }
+
+    fn parse<T: FromStr>(&self, field: &'static str, value: &str) -> Result<T, FontError> {
+        value.parse().map_err(|_| FontError::InvalidValue {
+            line: self.line,
+            field,
+            value: value.to_string(),
+        })
+    }
+}
+
+/// Text descriptors have one tag per line: `char id=65 x=0 ...`. Values may be
+/// quoted to contain spaces, as in `face="Shinonome Gothic"`.
+fn parse_text(text: &str) -> Result<Vec<Tag>, FontError> {
+    let mut tags = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let syntax_error = |message: String| FontError::Syntax {
+            line: line_number,
+            message,
+        };
+
+        let (name, mut rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let mut attributes = HashMap::new();
+        loop {
+            rest = rest.trim_start();
+            if rest.is_empty() {
+                break;
+            }
+            let (key, after) = rest
+                .split_once('=')
+                .filter(|(key, _)| !key.is_empty() && !key.contains(char::is_whitespace))
+                .ok_or_else(|| syntax_error(format!("expected `key=value` at {rest:?}")))?;
+            let (value, after) = match after.strip_prefix('"') {
+                Some(quoted) => {
+                    let end = quoted
+                        .find('"')
+                        .ok_or_else(|| syntax_error(format!("unterminated quote in `{key}`")))?;
+                    (&quoted[..end], &quoted[end + 1..])
+                }
+                None => after.split_once(char::is_whitespace).unwrap_or((after, "")),
+            };
+            attributes.insert(key.to_string(), value.to_string());
+            rest = after;
+        }
+
+        tags.push(Tag {
+            line: line_number,
+            name: name.to_string(),
+            attributes,
+        });
+    }
+    Ok(tags)
+}
+
+/// XML descriptors nest the same tags inside `<font>`, `<pages>`, `<chars>`
+/// and `<kernings>`, which can simply be flattened.
+fn parse_xml(text: &str) -> Result<Vec<Tag>, FontError> {
+    let mut reader = Reader::from_str(text);
+    let mut lines = LineCounter::new(text);
+    let mut tags = Vec::new();
+    loop {
+        let start = reader.buffer_position() as usize;
+        let event = reader.read_event().map_err(|err| FontError::Syntax {
+            line: lines.line_at(reader.error_position() as usize),
+            message: err.to_string(),
+        })?;
+        let element = match event {
+            Event::Start(element) | Event::Empty(element) => element,
+            Event::Eof => break,
+            _ => continue,
+        };
+
+        let line = lines.line_at(start);
+        let mut attributes = HashMap::new();
+        for attribute in element.attributes() {
+            let attribute = attribute.map_err(|err| FontError::Syntax {
+                line,
+                message: err.to_string(),
+            })?;
+            let value = attribute.unescape_value().map_err(|err| FontError::Syntax {
+                line,
+                message: err.to_string(),
+            })?;
+            attributes.insert(
+                String::from_utf8_lossy(attribute.key.as_ref()).into_owned(),
+                value.into_owned(),
+            );
+        }
+        tags.push(Tag {
+            line,
+            name: String::from_utf8_lossy(element.name().as_ref()).into_owned(),
+            attributes,
+        });
+    }
+    Ok(tags)
+}
+
+/// Turns byte offsets into line numbers without rescanning the whole file for
+/// every one of the thousands of `char` elements.
+struct LineCounter<'a> {
+    text: &'a [u8],
+    offset: usize,
+    line: usize,
+}
+
+impl<'a> LineCounter<'a> {
+    fn new(text: &'a str) -> Self {
+        Self {
+            text: text.as_bytes(),
+            offset: 0,
+            line: 1,
+        }
+    }
+
+    fn line_at(&mut self, offset: usize) -> usize {
+        let offset = offset.min(self.text.len());
+        if offset < self.offset {
+            self.offset = 0;
+            self.line = 1;
+        }
+        self.line += self.text[self.offset..offset].iter().filter(|&&byte| byte == b'\n').count();
+        self.offset = offset;
+        self.line
+    }
+}
+
+/// Binary descriptors (version 3) are a sequence of blocks, each a type byte,
+/// a little-endian `u32` size and the fixed-size records of that block.
+fn parse_binary(data: &[u8]) -> Result<BitmapFont, FontError> {
+    const COMMON_SIZE: usize = 15;
+    const CHAR_SIZE: usize = 20;
+    const KERNING_SIZE: usize = 10;
+
+    let (&version, mut rest) = data
+        .split_first()
+        .ok_or(FontError::TruncatedBlock { block: 0 })?;
+    if version != 3 {
+        return Err(FontError::UnsupportedBinaryVersion(version));
+    }
+
+    let mut builder = FontBuilder::default();
+    while let Some((&block, after)) = rest.split_first() {
+        let truncated = FontError::TruncatedBlock { block };
+        let size = match after.get(..4) {
+            Some(size) => u32::from_le_bytes([size[0], size[1], size[2], size[3]]) as usize,
+            None => return Err(truncated),
+        };
+        let Some(body) = after.get(4..4 + size) else {
+            return Err(truncated);
+        };
+        rest = &after[4 + size..];
+
+        match block {
+            2 => {
+                if body.len() < COMMON_SIZE {
+                    return Err(truncated);
+                }
+                builder.common = Some((
+                    read_u16(body, 0) as f32,
+                    read_u16(body, 4) as f32,
+                    read_u16(body, 6) as f32,
+                ));
+            }
+            3 => {
+                // Every page name has the same length and ends with a NUL.
+                let names = body.split(|&byte| byte == 0).filter(|name| !name.is_empty());
+                for (page, name) in names.enumerate() {
+                    builder
+                        .page_files
+                        .insert(page as u32, String::from_utf8_lossy(name).into_owned());
+                }
+            }
+            4 => {
+                if body.len() % CHAR_SIZE != 0 {
+                    return Err(truncated);
+                }
+                for record in body.chunks_exact(CHAR_SIZE) {
+                    let glyph = Glyph {
+                        x: read_u16(record, 4) as f32,
+                        y: read_u16(record, 6) as f32,
+                        width: read_u16(record, 8) as f32,
+                        height: read_u16(record, 10) as f32,
+                        x_offset: read_u16(record, 12) as i16 as f32,
+                        y_offset: read_u16(record, 14) as i16 as f32,
+                        x_advance: read_u16(record, 16) as i16 as f32,
+                        page: record[18] as u32,
+                    };
+                    builder.glyphs.insert(read_u32(record, 0), glyph);
+                }
+            }
+            5 => {
+                if body.len() % KERNING_SIZE != 0 {
+                    return Err(truncated);
+                }
+                for record in body.chunks_exact(KERNING_SIZE) {
+                    let amount = read_u16(record, 8) as i16 as f32;
+                    builder
+                        .kerning
+                        .insert((read_u32(record, 0), read_u32(record, 4)), amount);
+                }
+            }
+            // Block 1 is the `info` block, which nothing here needs.
+            _ => {}
+        }
+    }
+    builder.finish()
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+#[derive(Default)]
+struct FontBuilder {
+    /// Line height and texture size.
+    common: Option<(f32, f32, f32)>,
+    glyphs: HashMap<u32, Glyph>,
+    page_files: HashMap<u32, String>,
+    kerning: HashMap<(u32, u32), f32>,
+}
+
+impl FontBuilder {
+    fn add_tag(&mut self, tag: &Tag) -> Result<(), FontError> {
+        match tag.name.as_str() {
+            "common" => {
+                self.common = Some((tag.get("lineHeight")?, tag.get("scaleW")?, tag.get("scaleH")?));
+            }
+            "page" => {
+                self.page_files.insert(tag.get("id")?, tag.get("file")?);
+            }
+            "char" => {
+                let glyph = Glyph {
+                    x: tag.get("x")?,
+                    y: tag.get("y")?,
+                    width: tag.get("width")?,
+                    height: tag.get("height")?,
+                    x_offset: tag.get("xoffset")?,
+                    y_offset: tag.get("yoffset")?,
+                    x_advance: tag.get("xadvance")?,
+                    page: tag.get_or("page", 0)?,
+                };
+                self.glyphs.insert(tag.get("id")?, glyph);
+            }
+            "kerning" => {
+                self.kerning
+                    .insert((tag.get("first")?, tag.get("second")?), tag.get("amount")?);
+            }
+            // `info`, the `chars`/`kernings` counts and the XML wrappers.
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<BitmapFont, FontError> {
+        let (line_height, texture_width, texture_height) = self.common.ok_or(FontError::MissingCommon)?;
+        let space_advance = self
+            .glyphs
+            .get(&32)
+            .map(|g| g.x_advance)
+            .unwrap_or(line_height * 0.25);
+
+        // A pair with a glyph this font lacks would otherwise apply to whichever
+        // fallback font ends up drawing it.
+        let glyphs = &self.glyphs;
+        self.kerning
+            .retain(|(first, second), _| glyphs.contains_key(first) && glyphs.contains_key(second));
+
+        Ok(BitmapFont {
+            line_height,
+            texture_width,
+            texture_height,
+            glyphs: self.glyphs,
+            page_files: self.page_files,
+            space_advance,
+            kerning: self.kerning,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMMON: &str = "common lineHeight=16 base=12 scaleW=128 scaleH=64 pages=1 packed=0";
+
+    #[test]
+    fn text_values_may_be_quoted() {
+        let text = format!(
+            "info face=\"Shinonome Gothic\" size=16 bold=0\n\
+             {COMMON}\n\
+             page id=0 file=\"Shinonome Gothic_0.png\"\n\
+             chars count=1\n\
+             char id=65   x=2 y=3 width=7 height=9 xoffset=-1 yoffset=2 xadvance=8 page=0 chnl=15\n"
+        );
+        let tags = parse_text(&text).unwrap();
+        assert_eq!(tags[0].attributes["face"], "Shinonome Gothic");
+        assert_eq!(tags[0].attributes["size"], "16");
+
+        let font = parse_bitmap_font(text.as_bytes()).unwrap();
+        assert_eq!(font.page_files[&0], "Shinonome Gothic_0.png");
+        let glyph = &font.glyphs[&65];
+        assert_eq!(
+            (glyph.x, glyph.y, glyph.width, glyph.height),
+            (2.0, 3.0, 7.0, 9.0)
+        );
+        assert_eq!(
+            (glyph.x_offset, glyph.y_offset, glyph.x_advance),
+            (-1.0, 2.0, 8.0)
+        );
+    }
+
+    #[test]
+    fn parses_the_bundled_fonts() {
+        for data in [
+            &include_bytes!("../assets/MusicTitleFont.fnt")[..],
+            &include_bytes!("../assets/ShinonomeGothic.fnt")[..],
+            &include_bytes!("../assets/Ramche.fnt")[..],
+        ] {
+            let font = parse_bitmap_font(data).unwrap();
+            assert!(font.line_height > 0.0);
+            assert!(!font.glyphs.is_empty());
+            assert!(!font.page_files.is_empty());
+        }
+    }
+
+    #[test]
+    fn text_reports_unterminated_quotes() {
+        let err = parse_bitmap_font(b"info face=\"Shinonome Gothic size=16").unwrap_err();
+        assert_eq!(err.to_string(), "line 1: unterminated quote in `face`");
+    }
+
+    #[test]
+    fn parses_xml_descriptors() {
+        let xml = r#"<?xml version="1.0"?>
+<font>
+  <info face="Ramche" size="12"/>
+  <common lineHeight="14" base="11" scaleW="256" scaleH="128" pages="1"/>
+  <pages>
+    <page id="0" file="Ramche &amp; Co_0.png"/>
+  </pages>
+  <chars count="2">
+    <char id="44032" x="10" y="20" width="11" height="12" xoffset="0" yoffset="1" xadvance="12" page="0"/>
+    <char id="44033" x="22" y="20" width="11" height="12" xoffset="0" yoffset="1" xadvance="12"/>
+  </chars>
+  <kernings count="2">
+    <kerning first="44032" second="44033" amount="-1"/>
+    <kerning first="44032" second="65" amount="-2"/>
+  </kernings>
+</font>
+"#;
+        let font = parse_bitmap_font(xml.as_bytes()).unwrap();
+        assert_eq!(
+            (font.line_height, font.texture_width, font.texture_height),
+            (14.0, 256.0, 128.0)
+        );
+        assert_eq!(font.page_files[&0], "Ramche & Co_0.png");
+        assert_eq!(font.glyphs.len(), 2);
+        assert_eq!(font.glyphs[&44033].x, 22.0);
+        assert_eq!(font.kerning[&(44032, 44033)], -1.0);
+        // `A` is not in the font, so its pair is dropped.
+        assert_eq!(font.kerning.len(), 1);
+        // Without a space glyph the advance is a quarter of the line height.
+        assert_eq!(font.space_advance, 3.5);
+    }
+
+    #[test]
+    fn reports_lines_of_missing_fields() {
+        let text =
+            format!("{COMMON}\n\nchar id=65 x=0 width=7 height=9 xoffset=0 yoffset=0 xadvance=8\n");
+        let err = parse_bitmap_font(text.as_bytes()).unwrap_err();
+        assert!(matches!(
+            &err,
+            FontError::MissingField { line: 3, tag, field: "y" } if tag == "char"
+        ));
+        assert_eq!(err.to_string(), "line 3: `char` is missing `y`");
+
+        let xml = r#"<font>
+  <common lineHeight="14" scaleW="256"
+    scaleH="128"/>
+  <page file="a.png"/>
+</font>"#;
+        let err = parse_bitmap_font(xml.as_bytes()).unwrap_err();
+        assert!(matches!(
+            &err,
+            FontError::MissingField { line: 4, tag, field: "id" } if tag == "page"
+        ));
+    }
+
+    #[test]
+    fn reports_lines_of_invalid_values() {
+        let text = format!(
+            "{COMMON}\nchar id=65 x=0 y=zero width=7 height=9 xoffset=0 yoffset=0 xadvance=8\n"
+        );
+        let err = parse_bitmap_font(text.as_bytes()).unwrap_err();
+        assert!(matches!(
+            &err,
+            FontError::InvalidValue { line: 2, field: "y", value } if value == "zero"
+        ));
+        assert_eq!(err.to_string(), "line 2: invalid value \"zero\" for `y`");
+
+        let xml =
+            "<font>\n\n  <common lineHeight=\"1.5.0\" scaleW=\"256\" scaleH=\"128\"/>\n</font>";
+        let err = parse_bitmap_font(xml.as_bytes()).unwrap_err();
+        assert!(matches!(
+            &err,
+            FontError::InvalidValue { line: 3, field: "lineHeight", value } if value == "1.5.0"
+        ));
+    }
+
+    #[test]
+    fn requires_a_common_tag() {
+        let err = parse_bitmap_font(b"info face=x\n").unwrap_err();
+        assert!(matches!(err, FontError::MissingCommon));
+    }
+
+    fn block(kind: u8, body: &[u8]) -> Vec<u8> {
+        let mut block = vec![kind];
+        block.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        block.extend_from_slice(body);
+        block
+    }
+
+    fn binary_char(id: u32, x: u16, x_offset: i16, x_advance: i16, page: u8) -> Vec<u8> {
+        let mut record = id.to_le_bytes().to_vec();
+        for value in [x, 4, 6, 10] {
+            record.extend_from_slice(&value.to_le_bytes());
+        }
+        for value in [x_offset, -2, x_advance] {
+            record.extend_from_slice(&value.to_le_bytes());
+        }
+        record.extend_from_slice(&[page, 15]);
+        record
+    }
+
+    #[test]
+    fn parses_binary_descriptors() {
+        let mut common = Vec::new();
+        for value in [18u16, 14, 512, 256, 2] {
+            common.extend_from_slice(&value.to_le_bytes());
+        }
+        common.extend_from_slice(&[0, 0, 4, 4, 4]);
+
+        let mut chars = binary_char(32, 0, 0, 5, 0);
+        chars.extend(binary_char(0x266a, 40, -1, 12, 1));
+
+        let mut kerning = Vec::new();
+        kerning.extend_from_slice(&32u32.to_le_bytes());
+        kerning.extend_from_slice(&0x266au32.to_le_bytes());
+        kerning.extend_from_slice(&(-3i16).to_le_bytes());
+
+        let mut data = b"BMF\x03".to_vec();
+        data.extend(block(1, b"\x10\x00\x00\x00face\0"));
+        data.extend(block(2, &common));
+        data.extend(block(3, b"font_0.png\0font_1.png\0"));
+        data.extend(block(4, &chars));
+        data.extend(block(5, &kerning));
+
+        let font = parse_bitmap_font(&data).unwrap();
+        assert_eq!(
+            (font.line_height, font.texture_width, font.texture_height),
+            (18.0, 512.0, 256.0)
+        );
+        assert_eq!(font.page_files[&0], "font_0.png");
+        assert_eq!(font.page_files[&1], "font_1.png");
+        assert_eq!(font.space_advance, 5.0);
+        let note = &font.glyphs[&0x266a];
+        assert_eq!(
+            (note.x, note.y, note.width, note.height),
+            (40.0, 4.0, 6.0, 10.0)
+        );
+        assert_eq!(
+            (note.x_offset, note.y_offset, note.x_advance),
+            (-1.0, -2.0, 12.0)
+        );
+        assert_eq!(note.page, 1);
+        assert_eq!(font.kerning[&(32, 0x266a)], -3.0);
+    }
+
+    #[test]
+    fn rejects_other_binary_versions_and_short_blocks() {
+        let err = parse_bitmap_font(b"BMF\x02").unwrap_err();
+        assert!(matches!(err, FontError::UnsupportedBinaryVersion(2)));
+
+        let mut data = b"BMF\x03".to_vec();
+        data.extend(block(4, &[0; 19]));
+        let err = parse_bitmap_font(&data).unwrap_err();
+        assert!(matches!(err, FontError::TruncatedBlock { block: 4 }));
+
+        let mut data = b"BMF\x03".to_vec();
+        data.extend_from_slice(&[2, 15, 0, 0, 0, 18, 0]);
+        let err = parse_bitmap_font(&data).unwrap_err();
+        assert!(matches!(err, FontError::TruncatedBlock { block: 2 }));
+    }
+}
@@ -0,0 +1,123 @@
+use crate::backend::simulate;
+use crate::bgra_to_straight_rgba;
+use crate::media::{MediaInfo, MediaStatus};
+use crate::overlay::OverlayCore;
+use anyhow::{anyhow, Context, Result};
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, Frame, RgbaImage};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+/// Upper bound for one appear, hold and disappear cycle, in case the settings
+/// never let the card hide.
+const MAX_CYCLE: f64 = 60.0;
+
+pub(crate) struct RenderOptions {
+    pub(crate) title: String,
+    pub(crate) artist: String,
+    pub(crate) art_url: String,
+    pub(crate) out: PathBuf,
+    pub(crate) fps: u16,
+    /// Seconds the card stays fully visible before it hides again.
+    pub(crate) hold: f32,
+}
+
+/// Renders one full appear, hold and disappear cycle of the title card and
+/// encodes it as APNG or GIF, depending on the extension of `out`.
+pub(crate) fn render_title_card(core: &mut OverlayCore, options: RenderOptions) -> Result<()> {
+    let format = AnimationFormat::from_path(&options.out)?;
+    if options.fps == 0 {
+        return Err(anyhow!("--fps must be positive"));
+    }
+
+    core.settings.hide_automatically = Some(options.hold);
+    let events = [(
+        0.0,
+        MediaInfo {
+            title: options.title,
+            artist: options.artist,
+            status: MediaStatus::Playing,
+            art_url: options.art_url,
+        },
+    )];
+    let fps = options.fps as f64;
+
+    // The surface grows and shrinks with the text on screen, but every frame
+    // of an animation has to be the same size, so find the largest one first.
+    let (mut width, mut height) = (1, 1);
+    simulate(core, &events, fps, MAX_CYCLE, |core, time| {
+        let (frame_width, frame_height) = core.canvas_size(1.0);
+        width = width.max(frame_width);
+        height = height.max(frame_height);
+        Ok(time == 0.0 || !core.is_idle())
+    })?;
+
+    core.reset();
+    let mut frames = Vec::new();
+    let mut canvas = vec![0u8; (width * height * 4) as usize];
+    simulate(core, &events, fps, MAX_CYCLE, |core, time| {
+        core.render(&mut canvas, width, height, 1.0);
+        frames.push(bgra_to_straight_rgba(&canvas));
+        Ok(time == 0.0 || !core.is_idle())
+    })?;
+
+    match format {
+        AnimationFormat::Apng => write_apng(&options.out, width, height, options.fps, &frames),
+        AnimationFormat::Gif => write_gif(&options.out, width, height, options.fps, frames),
+    }
+    .with_context(|| format!("Failed to write {}", options.out.display()))
+}
+
+enum AnimationFormat {
+    Apng,
+    Gif,
+}
+
+impl AnimationFormat {
+    fn from_path(path: &Path) -> Result<Self> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+        match extension.as_deref() {
+            Some("apng" | "png") => Ok(Self::Apng),
+            Some("gif") => Ok(Self::Gif),
+            _ => Err(anyhow!(
+                "don't know how to encode {}, use a .apng, .png or .gif file",
+                path.display()
+            )),
+        }
+    }
+}
+
+fn write_apng(path: &Path, width: u32, height: u32, fps: u16, frames: &[Vec<u8>]) -> Result<()> {
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_animated(frames.len() as u32, 0)?;
+    encoder.set_frame_delay(1, fps)?;
+    let mut writer = encoder.write_header()?;
+    for frame in frames {
+        writer.write_image_data(frame)?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+/// GIF only knows fully transparent or opaque pixels, so anything under half
+/// opacity is dropped and the fades turn into cuts. APNG keeps the real alpha.
+fn write_gif(path: &Path, width: u32, height: u32, fps: u16, frames: Vec<Vec<u8>>) -> Result<()> {
+    let mut encoder = GifEncoder::new(BufWriter::new(File::create(path)?));
+    encoder.set_repeat(Repeat::Infinite)?;
+    let delay = Delay::from_numer_denom_ms(1000, fps as u32);
+    for mut pixels in frames {
+        for pixel in pixels.chunks_exact_mut(4) {
+            pixel[3] = if pixel[3] < 128 { 0 } else { 255 };
+        }
+        let image = RgbaImage::from_raw(width, height, pixels)
+            .ok_or_else(|| anyhow!("frame does not match {width}x{height}"))?;
+        encoder.encode_frame(Frame::from_parts(image, 0, 0, delay))?;
+    }
+    Ok(())
+}
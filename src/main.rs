@@ -1,42 +1,81 @@
+mod album_art;
+mod assets;
+mod backend;
+mod bmfont;
+mod export;
+mod media;
+mod overlay;
+mod panel;
+mod ttf;
+
 use anyhow::{anyhow, Result};
-use minifb::{Icon as MinifbIcon, Window, WindowOptions};
-use mpris::{PlaybackStatus, PlayerFinder};
+use assets::{resolve_asset, Asset};
+use backend::{HeadlessOptions, WaylandBackend, X11Backend};
+use bmfont::load_bitmap_font;
+use export::RenderOptions;
+use media::{MediaSourceKind, MediaSourceOptions};
+use overlay::{OverlayCore, PresentationBackend};
 use serde::{Deserialize, Serialize};
-use smithay_client_toolkit::{
-    compositor::{CompositorHandler, CompositorState},
-    delegate_compositor, delegate_layer, delegate_output, delegate_registry, delegate_shm,
-    output::{OutputHandler, OutputState},
-    registry::{ProvidesRegistryState, RegistryState},
-    registry_handlers,
-    shell::wlr_layer::{
-        Anchor, KeyboardInteractivity, Layer, LayerShell, LayerShellHandler, LayerSurface,
-        LayerSurfaceConfigure,
-    },
-    shell::WaylandSurface,
-    shm::{slot::SlotPool, Shm, ShmHandler},
-};
-use std::collections::HashMap;
+use smithay_client_toolkit::shell::wlr_layer::Anchor;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::mpsc;
 use std::time::{Duration, Instant, SystemTime};
 use gtk::glib::{self, ControlFlow, Propagation};
 use gtk::prelude::*;
 use tray_icon::menu::{Menu, MenuEvent, MenuItem};
 use tray_icon::{Icon, TrayIconBuilder};
-use wayland_client::{
-    globals::registry_queue_init,
-    protocol::{wl_output, wl_shm, wl_surface},
-    Connection, QueueHandle,
-};
 
 fn main() -> Result<()> {
     let args: Vec<String> = std::env::args().collect();
+    if args.iter().any(|arg| arg == "--builtin-assets") {
+        assets::use_builtin_assets_only();
+    }
     let settings_path = get_arg_value(&args, "--settings-path")
         .map(PathBuf::from)
         .unwrap_or_else(default_settings_path);
 
     let settings = Settings::load(&settings_path).unwrap_or_default();
+    if args.get(1).map(String::as_str) == Some("render") {
+        let options = RenderOptions {
+            title: get_arg_value(&args, "--title").unwrap_or_default(),
+            artist: get_arg_value(&args, "--artist").unwrap_or_default(),
+            art_url: get_arg_value(&args, "--art-url").unwrap_or_default(),
+            out: get_arg_value(&args, "--out")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from("card.apng")),
+            fps: get_arg_value(&args, "--fps")
+                .map(|value| value.parse().map_err(|err| anyhow!("invalid value {value:?} for --fps: {err}")))
+                .transpose()?
+                .unwrap_or(30),
+            hold: get_arg_f64(&args, "--hold")?
+                .map(|hold| hold as f32)
+                .or(settings.hide_automatically)
+                .unwrap_or(settings.animation.stay_time),
+        };
+        let (font, atlas) = load_assets(&settings.fonts);
+        let settings_state = SettingsState::new(&settings_path);
+        let mut core = OverlayCore::new(settings_path, settings, settings_state, font, atlas);
+        return export::render_title_card(&mut core, options);
+    }
+    if args.iter().any(|arg| arg == "--headless") {
+        let at = get_arg_f64(&args, "--at")?;
+        let options = HeadlessOptions {
+            script: get_arg_value(&args, "--script").map(PathBuf::from),
+            out: get_arg_value(&args, "--out")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from(if at.is_some() { "frame.png" } else { "frames" })),
+            at,
+            fps: get_arg_f64(&args, "--fps")?.unwrap_or(30.0),
+            duration: get_arg_f64(&args, "--duration")?,
+        };
+        let (font, atlas) = load_assets(&settings.fonts);
+        let settings_state = SettingsState::new(&settings_path);
+        let mut core = OverlayCore::new(settings_path, settings, settings_state, font, atlas);
+        return backend::run_headless(&mut core, options);
+    }
+
     if !settings_path.exists() {
         if let Err(err) = ensure_settings_parent(&settings_path) {
             eprintln!("Failed to create settings directory: {err}");
@@ -48,209 +87,86 @@ fn main() -> Result<()> {
     }
     let settings_state = SettingsState::new(&settings_path);
 
+    let source_kind = match get_arg_value(&args, "--source") {
+        Some(value) => MediaSourceKind::parse(&value)
+            .ok_or_else(|| anyhow!("unknown media source {value:?}, expected mpris, mpd, stdin or file"))?,
+        None => settings.media_source,
+    };
+    let source = media::create_media_source(MediaSourceOptions {
+        kind: source_kind,
+        settings_path: settings_path.clone(),
+        mpris_bus_address: get_arg_value(&args, "--mpris-bus-address"),
+        mpd_address: get_arg_value(&args, "--mpd-address").unwrap_or_else(|| settings.mpd_address.clone()),
+        mpd_password: settings.mpd_password.clone(),
+        media_file: get_arg_value(&args, "--media-file")
+            .map(PathBuf::from)
+            .or_else(|| settings.media_file.clone()),
+    })?;
+
     let (tx, rx) = mpsc::channel();
-    std::thread::spawn(move || mpris_loop(tx));
+    media::spawn_media_source(source, tx);
 
     start_tray(settings_path.clone());
 
     let has_wayland = std::env::var("WAYLAND_DISPLAY").map(|v| !v.is_empty()).unwrap_or(false);
     let has_x11 = std::env::var("DISPLAY").map(|v| !v.is_empty()).unwrap_or(false);
 
-    if has_wayland {
-        run_wayland(settings_path, settings, settings_state, rx)
-    } else if has_x11 {
-        run_x11(settings_path, settings, settings_state, rx)
-    } else {
+    if !has_wayland && !has_x11 {
         eprintln!("No supported display server found (WAYLAND_DISPLAY or DISPLAY).");
-        Ok(())
-    }
-}
-
-fn run_wayland(
-    settings_path: PathBuf,
-    settings: Settings,
-    settings_state: SettingsState,
-    rx: Receiver<MediaInfo>,
-) -> Result<()> {
-    let conn = Connection::connect_to_env()?;
-    let (globals, mut event_queue) = registry_queue_init(&conn)?;
-    let qh = event_queue.handle();
-
-    let compositor = CompositorState::bind(&globals, &qh).expect("wl_compositor unavailable");
-    let layer_shell = LayerShell::bind(&globals, &qh).expect("layer-shell unavailable");
-    let shm = Shm::bind(&globals, &qh).expect("wl_shm unavailable");
-
-    let surface = compositor.create_surface(&qh);
-    let layer = layer_shell.create_layer_surface(&qh, surface, Layer::Overlay, Some("deltatune"), None);
-    layer.set_anchor(Anchor::TOP | Anchor::LEFT);
-    layer.set_margin(settings.y_pos, 0, 0, settings.x_pos);
-    layer.set_keyboard_interactivity(KeyboardInteractivity::None);
-    layer.set_exclusive_zone(-1);
-    layer.set_size(1, 1);
-    layer.commit();
-
-    let (font, atlas) = load_assets();
-    let pool = SlotPool::new(4, &shm).expect("Failed to create slot pool");
-
-    let mut app = OverlayApp {
-        registry_state: RegistryState::new(&globals),
-        output_state: OutputState::new(&globals, &qh),
-        shm,
-        layer,
-        pool,
-        width: 1,
-        height: 1,
-        exit: false,
-        first_configure: true,
-        last_frame: Instant::now(),
-        settings_path,
-        settings,
-        settings_state,
-        font,
-        atlas,
-        media: MediaState::default(),
-        media_rx: rx,
-        display: DisplayController::new(),
-    };
-
-    loop {
-        event_queue.blocking_dispatch(&mut app)?;
-        if app.exit {
-            break;
-        }
+        return Ok(());
     }
 
-    Ok(())
-}
-
-fn run_x11(
-    settings_path: PathBuf,
-    settings: Settings,
-    settings_state: SettingsState,
-    rx: Receiver<MediaInfo>,
-) -> Result<()> {
-    let (font, atlas) = load_assets();
-    let icon_data = load_icon_buffer();
-    let (window_w, window_h) = compute_x11_window_size(&settings, &font);
-    let mut app = X11App::new(
-        settings_path,
-        settings,
-        settings_state,
-        font,
-        atlas,
-        rx,
-        window_w,
-        window_h,
-    );
-
-    app.draw();
-
-    let window_options = WindowOptions {
-        borderless: true,
-        title: false,
-        resize: false,
-        topmost: true,
-        transparency: true,
-        ..WindowOptions::default()
+    let (font, atlas) = load_assets(&settings.fonts);
+    let mut core = OverlayCore::new(settings_path, settings, settings_state, font, atlas);
+    let mut backend: Box<dyn PresentationBackend> = if has_wayland {
+        Box::new(WaylandBackend::new(&core.settings)?)
+    } else {
+        Box::new(X11Backend::new(&core)?)
     };
-    let mut window = Window::new(
-        "DeltaTune",
-        window_w as usize,
-        window_h as usize,
-        window_options,
-    )?;
-    if let Some(ref data) = icon_data {
-        if let Ok(icon) = MinifbIcon::try_from(data.as_slice()) {
-            window.set_icon(icon);
-        }
-    }
-    apply_x11_overlay_hints(&window);
-    window.set_position(app.settings.x_pos as isize, app.settings.y_pos as isize);
-    window.limit_update_rate(Some(Duration::from_micros(16_666)));
-
-    while window.is_open() {
-        app.draw();
-        window.set_position(app.settings.x_pos as isize, app.settings.y_pos as isize);
-        window.update_with_buffer(&app.pixels, window_w as usize, window_h as usize)?;
-    }
-
-    Ok(())
+    overlay::run_overlay(&mut core, backend.as_mut(), rx)
 }
 
-fn load_assets() -> (BitmapFont, FontAtlas) {
-    let font_path = resolve_font_asset_path("MusicTitleFont.fnt")
-        .unwrap_or_else(|| PathBuf::from("assets/MusicTitleFont.fnt"));
-    let texture_path = default_texture_path(&font_path);
+/// Loads the first of `fonts` as the primary font and merges the others in
+/// as fallbacks for the glyphs it lacks.
+fn load_assets(fonts: &[String]) -> (BitmapFont, FontAtlas) {
+    let mut fonts = fonts.iter().map(String::as_str);
+    let primary_name = fonts.next().unwrap_or(DEFAULT_FONTS[0]);
+    let font_asset = resolve_asset(primary_name).unwrap_or_else(|| Asset::File(PathBuf::from(primary_name)));
 
-    let mut font = load_bitmap_font(&font_path).unwrap_or_else(|err| {
-        eprintln!("Failed to load primary bitmap font {}: {err}", font_path.display());
+    let mut font = load_bitmap_font(&font_asset).unwrap_or_else(|err| {
+        eprintln!("Failed to load primary bitmap font {font_asset}: {err}");
         BitmapFont::fallback()
     });
-    let mut atlas = FontAtlas::load(&font_path, &texture_path, &mut font).unwrap_or_else(|err| {
-        eprintln!(
-            "Failed to load primary font atlas for {}: {err}",
-            font_path.display()
-        );
+    let mut atlas = FontAtlas::load(&font_asset, &mut font).unwrap_or_else(|err| {
+        eprintln!("Failed to load primary font atlas for {font_asset}: {err}");
         FontAtlas::empty()
     });
 
-    for fallback_name in ["ShinonomeGothic.fnt", "Ramche.fnt"] {
-        let Some(fallback_path) = resolve_font_asset_path(fallback_name) else {
+    for fallback_name in fonts {
+        let Some(fallback_asset) = resolve_asset(fallback_name) else {
+            eprintln!("Fallback bitmap font {fallback_name} not found");
             continue;
         };
-        let fallback_texture_path = default_texture_path(&fallback_path);
-        let mut fallback_font = match load_bitmap_font(&fallback_path) {
+        let mut fallback_font = match load_bitmap_font(&fallback_asset) {
             Ok(font) => font,
             Err(err) => {
-                eprintln!(
-                    "Failed to load fallback bitmap font {}: {err}",
-                    fallback_path.display()
-                );
+                eprintln!("Failed to load fallback bitmap font {fallback_asset}: {err}");
+                continue;
+            }
+        };
+        let fallback_atlas = match FontAtlas::load(&fallback_asset, &mut fallback_font) {
+            Ok(atlas) => atlas,
+            Err(err) => {
+                eprintln!("Failed to load fallback atlas for {fallback_asset}: {err}");
                 continue;
             }
         };
-        let fallback_atlas =
-            match FontAtlas::load(&fallback_path, &fallback_texture_path, &mut fallback_font) {
-                Ok(atlas) => atlas,
-                Err(err) => {
-                    eprintln!(
-                        "Failed to load fallback atlas for {}: {err}",
-                        fallback_path.display()
-                    );
-                    continue;
-                }
-            };
         merge_fallback_font(&mut font, &mut atlas, fallback_font, fallback_atlas);
     }
 
     (font, atlas)
 }
 
-fn resolve_font_asset_path(filename: &str) -> Option<PathBuf> {
-    let installed_path = PathBuf::from("/usr/share/deltatune").join(filename);
-    if installed_path.exists() {
-        return Some(installed_path);
-    }
-
-    let local_path = PathBuf::from("assets").join(filename);
-    if local_path.exists() {
-        return Some(local_path);
-    }
-
-    None
-}
-
-fn default_texture_path(font_path: &Path) -> PathBuf {
-    let stem = font_path
-        .file_stem()
-        .and_then(|value| value.to_str())
-        .unwrap_or("MusicTitleFont");
-    font_path
-        .parent()
-        .unwrap_or_else(|| Path::new("."))
-        .join(format!("{stem}.png"))
-}
-
 fn merge_fallback_font(
     base_font: &mut BitmapFont,
     base_atlas: &mut FontAtlas,
@@ -265,12 +181,22 @@ fn merge_fallback_font(
             .insert(page_id.saturating_add(page_offset), page);
     }
 
+    let mut merged = HashSet::new();
     for (codepoint, mut glyph) in fallback_font.glyphs {
         if base_font.glyphs.contains_key(&codepoint) {
             continue;
         }
         glyph.page = glyph.page.saturating_add(page_offset);
         base_font.glyphs.insert(codepoint, glyph);
+        merged.insert(codepoint);
+    }
+
+    // Kerning is only meaningful between glyphs of the same font, so only
+    // keep pairs where both glyphs were taken from this fallback.
+    for ((first, second), amount) in fallback_font.kerning {
+        if merged.contains(&first) && merged.contains(&second) {
+            base_font.kerning.insert((first, second), amount);
+        }
     }
 
     for (page_id, file) in fallback_font.page_files {
@@ -284,14 +210,9 @@ fn merge_fallback_font(
 }
 
 fn load_icon_buffer() -> Option<Vec<u64>> {
-    let icon_path = PathBuf::from("/usr/share/deltatune/deltatune.png");
-    let icon_path = if icon_path.exists() {
-        icon_path
-    } else {
-        PathBuf::from("assets/deltatune.png")
-    };
+    let icon = resolve_asset("deltatune.png")?.read().ok()?;
 
-    let image = image::open(&icon_path).ok()?.to_rgba8();
+    let image = image::load_from_memory(&icon).ok()?.to_rgba8();
     let (width, height) = image.dimensions();
     let mut data = Vec::with_capacity((width * height + 2) as usize);
     data.push(width as u64);
@@ -308,69 +229,6 @@ fn load_icon_buffer() -> Option<Vec<u64>> {
     Some(data)
 }
 
-fn compute_x11_window_size(settings: &Settings, font: &BitmapFont) -> (u32, u32) {
-    let (screen_w, _) = x11_screen_size().unwrap_or((800, 600));
-    let scale = settings.scale_factor * settings.text_scale;
-    let padding = 12.0;
-    let lines = if settings.show_artist_name { 2.0 } else { 1.0 };
-    let height = ((font.line_height * scale * lines + padding * 2.0) * settings.scale_y)
-        .max(1.0)
-        .round() as u32;
-    (screen_w.max(1), height.max(1))
-}
-
-fn x11_screen_size() -> Option<(u32, u32)> {
-    #[cfg(target_os = "linux")]
-    unsafe {
-        let xlib = x11_dl::xlib::Xlib::open().ok()?;
-        let display = (xlib.XOpenDisplay)(std::ptr::null());
-        if display.is_null() {
-            return None;
-        }
-        let screen = (xlib.XDefaultScreen)(display);
-        let width = (xlib.XDisplayWidth)(display, screen).max(1) as u32;
-        let height = (xlib.XDisplayHeight)(display, screen).max(1) as u32;
-        (xlib.XCloseDisplay)(display);
-        Some((width, height))
-    }
-    #[cfg(not(target_os = "linux"))]
-    {
-        None
-    }
-}
-
-fn apply_x11_overlay_hints(window: &Window) {
-    #[cfg(target_os = "linux")]
-    unsafe {
-        let xlib = match x11_dl::xlib::Xlib::open() {
-            Ok(lib) => lib,
-            Err(_) => return,
-        };
-        let xfixes = match x11_dl::xfixes::Xlib::open() {
-            Ok(lib) => lib,
-            Err(_) => return,
-        };
-        let display = (xlib.XOpenDisplay)(std::ptr::null());
-        if display.is_null() {
-            return;
-        }
-        let window_id = window.get_window_handle() as x11_dl::xlib::Window;
-
-        let mut hints: x11_dl::xlib::XWMHints = std::mem::zeroed();
-        hints.flags = x11_dl::xlib::InputHint;
-        hints.input = 0;
-        (xlib.XSetWMHints)(display, window_id, &mut hints);
-
-        let region = (xfixes.XFixesCreateRegion)(display, std::ptr::null_mut(), 0);
-        const SHAPE_INPUT: i32 = 2;
-        (xfixes.XFixesSetWindowShapeRegion)(display, window_id, SHAPE_INPUT, 0, 0, region);
-        (xfixes.XFixesDestroyRegion)(display, region);
-
-        (xlib.XFlush)(display);
-        (xlib.XCloseDisplay)(display);
-    }
-}
-
 fn default_settings_path() -> PathBuf {
     std::env::var("HOME")
         .map(PathBuf::from)
@@ -387,6 +245,16 @@ fn get_arg_value(args: &[String], name: &str) -> Option<String> {
         .cloned()
 }
 
+fn get_arg_f64(args: &[String], name: &str) -> Result<Option<f64>> {
+    get_arg_value(args, name)
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|err| anyhow!("invalid value {value:?} for {name}: {err}"))
+        })
+        .transpose()
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(default)]
 struct Settings {
@@ -394,8 +262,11 @@ struct Settings {
     scale_x: f32,
     scale_y: f32,
     text_scale: f32,
+    hidpi_scaling: bool,
     x_pos: i32,
     y_pos: i32,
+    text_anchor: TextAnchor,
+    output: OutputSelection,
     show_artist_name: bool,
     show_playback_status: bool,
     show_debug_overlay: bool,
@@ -403,6 +274,46 @@ struct Settings {
     background_opacity: f32,
     hyprland_pin: bool,
     hide_automatically: Option<f32>,
+    /// Widest the overlay gets in pixels. Lines that don't fit are handled
+    /// according to `overflow`.
+    max_width: Option<u32>,
+    overflow: OverflowMode,
+    /// Separate width limits for the title and artist lines when truncating
+    /// or wrapping. Fall back to `max_width`.
+    title_max_width: Option<u32>,
+    artist_max_width: Option<u32>,
+    /// How many lines the title and artist may wrap onto.
+    title_max_lines: u32,
+    artist_max_lines: u32,
+    /// Marquee speed in pixels per second.
+    marquee_speed: f32,
+    /// Seconds the marquee rests at either end of the line.
+    marquee_pause: f32,
+    animation: AnimationSettings,
+    title_style: TextStyle,
+    artist_style: TextStyle,
+    panel: Option<Panel>,
+    album_art: Option<AlbumArt>,
+    /// Bitmap fonts by file name or path, the primary one first and then the
+    /// fallbacks for glyphs it lacks. File names are looked up in the XDG data
+    /// directories, see `asset_dirs`.
+    fonts: Vec<String>,
+    /// TrueType/OpenType fonts for characters the bitmap fonts don't have,
+    /// tried in order.
+    ttf_fallback_fonts: Vec<PathBuf>,
+    /// Coverage from 0 to 1 above which a fallback glyph pixel is drawn
+    /// solid; below it is left out. Without it glyphs stay antialiased.
+    ttf_threshold: Option<f32>,
+    /// Place fallback glyphs on whole font pixels like the bitmap glyphs.
+    ttf_pixel_snap: bool,
+    allowed_players: Vec<String>,
+    blocked_players: Vec<String>,
+    player_priority: Vec<String>,
+    player_tie_break: PlayerTieBreak,
+    media_source: MediaSourceKind,
+    mpd_address: String,
+    mpd_password: Option<String>,
+    media_file: Option<PathBuf>,
 }
 
 impl Default for Settings {
@@ -412,8 +323,11 @@ impl Default for Settings {
             scale_x: 1.0,
             scale_y: 1.0,
             text_scale: 1.0,
+            hidpi_scaling: true,
             x_pos: 0,
             y_pos: 0,
+            text_anchor: TextAnchor::default(),
+            output: OutputSelection::default(),
             show_artist_name: true,
             show_playback_status: false,
             show_debug_overlay: false,
@@ -421,147 +335,476 @@ impl Default for Settings {
             background_opacity: 0.0,
             hyprland_pin: false,
             hide_automatically: Some(2.5),
+            max_width: None,
+            overflow: OverflowMode::default(),
+            title_max_width: None,
+            artist_max_width: None,
+            title_max_lines: 2,
+            artist_max_lines: 1,
+            marquee_speed: 40.0,
+            marquee_pause: 1.5,
+            animation: AnimationSettings::default(),
+            title_style: TextStyle::default(),
+            artist_style: TextStyle::default(),
+            panel: None,
+            album_art: None,
+            fonts: DEFAULT_FONTS.iter().map(|name| name.to_string()).collect(),
+            ttf_fallback_fonts: Vec::new(),
+            ttf_threshold: None,
+            ttf_pixel_snap: true,
+            allowed_players: Vec::new(),
+            blocked_players: Vec::new(),
+            player_priority: Vec::new(),
+            player_tie_break: PlayerTieBreak::default(),
+            media_source: MediaSourceKind::default(),
+            mpd_address: "127.0.0.1:6600".to_string(),
+            mpd_password: None,
+            media_file: None,
         }
     }
 }
 
-impl Settings {
-    fn load(path: &Path) -> Result<Self> {
-        if !path.exists() {
-            return Ok(Self::default());
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum PlayerTieBreak {
+    /// Keep the player that comes first on the bus.
+    BusOrder,
+    /// Prefer the player that started playing last. Players are only timed
+    /// from when deltatune first sees them playing, so players that were
+    /// already playing at startup tie and fall back to bus order, as do
+    /// paused or stopped players.
+    #[default]
+    MostRecentlyPlaying,
+}
+
+const DEFAULT_FONTS: [&str; 3] = ["MusicTitleFont.fnt", "ShinonomeGothic.fnt", "Ramche.fnt"];
+
+/// What to do with a line of metadata that is wider than its limit.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum OverflowMode {
+    /// Scroll it back and forth.
+    #[default]
+    Marquee,
+    /// Cut it off with an ellipsis.
+    Truncate,
+    /// Continue on the next line, up to `title_max_lines`/`artist_max_lines`.
+    Wrap,
+}
+
+impl OverflowMode {
+    const ALL: [(Self, &'static str); 3] =
+        [(Self::Marquee, "marquee"), (Self::Truncate, "truncate"), (Self::Wrap, "wrap")];
+}
+
+/// Colors and size of the title or the artist lines.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+struct TextStyle {
+    /// Multiplied with the font atlas, which is white, so glyphs come out in
+    /// exactly this color.
+    color: Color,
+    /// Color at the bottom of every line, blending from `color` at the top.
+    gradient: Option<Color>,
+    /// Multiplied with the opacity of the fade animations.
+    opacity: f32,
+    /// Size relative to `text_scale`.
+    scale: f32,
+    /// Drawn around the glyphs so they stay readable on light backgrounds.
+    outline: Option<Outline>,
+    shadow: Option<Shadow>,
+}
+
+impl Default for TextStyle {
+    fn default() -> Self {
+        Self {
+            color: Color::WHITE,
+            gradient: None,
+            opacity: 1.0,
+            scale: 1.0,
+            outline: None,
+            shadow: None,
         }
-        let data = fs::read_to_string(path)?;
-        let settings = serde_json::from_str(&data)?;
-        Ok(settings)
     }
 }
 
-fn ensure_settings_parent(path: &Path) -> std::io::Result<()> {
-    if let Some(dir) = path.parent() {
-        fs::create_dir_all(dir)?;
+/// A border of `width` font pixels around every glyph.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+struct Outline {
+    width: u32,
+    color: Color,
+}
+
+impl Default for Outline {
+    fn default() -> Self {
+        Self {
+            width: 1,
+            color: Color::BLACK,
+        }
     }
-    Ok(())
 }
 
-struct SettingsState {
-    last_modified: Option<SystemTime>,
-    last_check: Instant,
+/// A copy of the text, and its outline, behind it, moved by `offset_x` and
+/// `offset_y` font pixels.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+struct Shadow {
+    offset_x: i32,
+    offset_y: i32,
+    color: Color,
+    opacity: f32,
 }
 
-impl SettingsState {
-    fn new(path: &Path) -> Self {
-        let last_modified = fs::metadata(path).and_then(|meta| meta.modified()).ok();
+impl Default for Shadow {
+    fn default() -> Self {
         Self {
-            last_modified,
-            last_check: Instant::now(),
+            offset_x: 1,
+            offset_y: 1,
+            color: Color::BLACK,
+            opacity: 0.75,
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-enum MediaStatus {
-    Playing,
-    Paused,
-    Stopped,
+/// A box behind the text, sized to it, like the dialog boxes in Deltarune.
+/// Sizes are in font pixels.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+struct Panel {
+    /// Space between the text and the border.
+    padding: u32,
+    color: Color,
+    opacity: f32,
+    border_width: u32,
+    border_color: Color,
+    corner_radius: u32,
+    /// A 9-slice PNG drawn instead of the box, looked up like the fonts.
+    frame: Option<String>,
+    /// How many pixels of each edge of `frame` are its border. The corners
+    /// are drawn as is, the edges and the middle are stretched.
+    frame_border: u32,
+}
+
+impl Default for Panel {
+    fn default() -> Self {
+        Self {
+            padding: 4,
+            color: Color::BLACK,
+            opacity: 1.0,
+            border_width: 2,
+            border_color: Color::WHITE,
+            corner_radius: 0,
+            frame: None,
+            frame_border: 4,
+        }
+    }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-struct MediaInfo {
-    title: String,
-    artist: String,
-    status: MediaStatus,
+impl Panel {
+    /// Room between the text and the outside of the panel on every side.
+    fn inset(&self) -> f32 {
+        let border = if self.frame.is_some() {
+            self.frame_border
+        } else {
+            self.border_width
+        };
+        (self.padding + border) as f32
+    }
 }
 
-impl Default for MediaInfo {
+/// The cover art of the track as a square next to the text. Sizes are in
+/// font pixels.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+struct AlbumArt {
+    position: ArtPosition,
+    size: u32,
+    /// Space between the art and the text.
+    gap: u32,
+    filter: ArtFilter,
+    /// Each pixel of the art covers this many font pixels, for a chunkier
+    /// look. 1 keeps the art as detailed as the text.
+    pixelation: u32,
+}
+
+impl Default for AlbumArt {
     fn default() -> Self {
         Self {
-            title: String::new(),
-            artist: String::new(),
-            status: MediaStatus::Stopped,
+            position: ArtPosition::default(),
+            size: 32,
+            gap: 4,
+            filter: ArtFilter::default(),
+            pixelation: 1,
         }
     }
 }
 
-struct MediaState {
-    info: MediaInfo,
-    last_update: Instant,
+impl AlbumArt {
+    /// Room the art takes up beside the text, in font pixels.
+    fn width(&self) -> f32 {
+        (self.size + self.gap) as f32
+    }
 }
 
-impl Default for MediaState {
-    fn default() -> Self {
+/// Which side of the text the album art goes on.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum ArtPosition {
+    #[default]
+    Left,
+    Right,
+}
+
+/// How the album art is shrunk to its size.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum ArtFilter {
+    /// Keeps one source pixel per pixel, which stays sharp but can flicker
+    /// between neighbouring colors.
+    Nearest,
+    /// Averages every block of source pixels.
+    #[default]
+    Pixelate,
+}
+
+/// How far the outline and shadow reach past the glyphs on each side.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct TextMargin {
+    left: f32,
+    top: f32,
+    right: f32,
+    bottom: f32,
+}
+
+impl TextMargin {
+    fn max(self, other: Self) -> Self {
+        Self {
+            left: self.left.max(other.left),
+            top: self.top.max(other.top),
+            right: self.right.max(other.right),
+            bottom: self.bottom.max(other.bottom),
+        }
+    }
+
+    fn scaled(self, scale: f32) -> Self {
         Self {
-            info: MediaInfo::default(),
-            last_update: Instant::now(),
+            left: self.left * scale,
+            top: self.top * scale,
+            right: self.right * scale,
+            bottom: self.bottom * scale,
         }
     }
 }
 
-fn mpris_loop(tx: Sender<MediaInfo>) {
-    let mut last_sent = MediaInfo::default();
-    loop {
-        match PlayerFinder::new() {
-            Ok(finder) => {
-                let players = finder.find_all().unwrap_or_default();
-                let mut best: Option<MediaInfo> = None;
-                for player in players {
-                    let status = match player.get_playback_status() {
-                        Ok(status) => status,
-                        Err(_) => continue,
-                    };
-                    let metadata = player.get_metadata().ok();
-                    let title = metadata
-                        .as_ref()
-                        .and_then(|m| m.title())
-                        .unwrap_or("")
-                        .to_string();
-                    let artist = metadata
-                        .as_ref()
-                        .and_then(|m| m.artists())
-                        .map(|artists| artists.join(", "))
-                        .unwrap_or_default();
-
-                    let info = MediaInfo {
-                        title,
-                        artist,
-                        status: map_status(status),
-                    };
-
-                    if best.as_ref().map_or(true, |current| is_better(&info, current)) {
-                        best = Some(info);
-                    }
-                }
+impl TextStyle {
+    /// The tint `t` of the way down a line, from 0 at the top to 1 at the
+    /// bottom, as RGB factors from 0 to 1.
+    fn tint_at(&self, t: f32) -> [f32; 3] {
+        let top = self.color.to_f32();
+        let Some(bottom) = self.gradient else {
+            return top;
+        };
+        let bottom = bottom.to_f32();
+        let t = t.clamp(0.0, 1.0);
+        [0, 1, 2].map(|i| top[i] + (bottom[i] - top[i]) * t)
+    }
+
+    /// Room the outline and shadow need around the text, in font pixels.
+    fn margin(&self) -> TextMargin {
+        let outline = self.outline.map_or(0.0, |outline| outline.width as f32);
+        let (shadow_x, shadow_y) = self
+            .shadow
+            .map_or((0.0, 0.0), |shadow| (shadow.offset_x as f32, shadow.offset_y as f32));
+        TextMargin {
+            left: outline + (-shadow_x).max(0.0),
+            top: outline + (-shadow_y).max(0.0),
+            right: outline + shadow_x.max(0.0),
+            bottom: outline + shadow_y.max(0.0),
+        }
+    }
+}
 
-                let next = best.unwrap_or_default();
-                if next != last_sent {
-                    let _ = tx.send(next.clone());
-                    last_sent = next;
-                }
-            }
-            Err(_) => {}
+/// An sRGB color, written as `"#rrggbb"` in the settings file.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+struct Color {
+    r: u8,
+    g: u8,
+    b: u8,
+}
+
+impl Color {
+    const WHITE: Self = Self { r: 255, g: 255, b: 255 };
+    const BLACK: Self = Self { r: 0, g: 0, b: 0 };
+
+    fn to_f32(self) -> [f32; 3] {
+        [self.r, self.g, self.b].map(|c| c as f32 / 255.0)
+    }
+}
+
+impl TryFrom<String> for Color {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let hex = value.strip_prefix('#').unwrap_or(&value);
+        let channel = |range: std::ops::Range<usize>| hex.get(range).and_then(|c| u8::from_str_radix(c, 16).ok());
+        match (hex.len(), channel(0..2), channel(2..4), channel(4..6)) {
+            (6, Some(r), Some(g), Some(b)) => Ok(Self { r, g, b }),
+            _ => Err(format!("invalid color {value:?}, expected \"#rrggbb\"")),
         }
+    }
+}
 
-        std::thread::sleep(Duration::from_millis(500));
+impl From<Color> for String {
+    fn from(color: Color) -> Self {
+        format!("#{:02x}{:02x}{:02x}", color.r, color.g, color.b)
     }
 }
 
-fn map_status(status: PlaybackStatus) -> MediaStatus {
-    match status {
-        PlaybackStatus::Playing => MediaStatus::Playing,
-        PlaybackStatus::Paused => MediaStatus::Paused,
-        PlaybackStatus::Stopped => MediaStatus::Stopped,
+/// Timings of the appear and disappear animations in seconds, and how far
+/// the text slides in font pixels.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+struct AnimationSettings {
+    mode: AnimationMode,
+    /// Wait before the new text appears while the old one is still hiding.
+    appear_delay: f32,
+    appear_duration: f32,
+    disappear_duration: f32,
+    /// How long paused or stopped media stays up when `hide_automatically` is off.
+    stay_time: f32,
+    slide_in_distance: f32,
+    slide_out_distance: f32,
+    appear_easing: Easing,
+    disappear_easing: Easing,
+    /// Which way the text moves while it appears.
+    appear_direction: SlideDirection,
+    /// Which way the text moves while it disappears.
+    disappear_direction: SlideDirection,
+    /// Characters revealed per second in typewriter mode.
+    typewriter_speed: f32,
+    /// Extra seconds the typewriter waits after punctuation.
+    typewriter_pause: f32,
+}
+
+impl Default for AnimationSettings {
+    fn default() -> Self {
+        Self {
+            mode: AnimationMode::default(),
+            appear_delay: 0.5,
+            appear_duration: 0.75,
+            disappear_duration: 0.75,
+            stay_time: 2.5,
+            slide_in_distance: 24.0,
+            slide_out_distance: 24.0,
+            appear_easing: Easing::default(),
+            disappear_easing: Easing::default(),
+            appear_direction: SlideDirection::default(),
+            disappear_direction: SlideDirection::default(),
+            typewriter_speed: 30.0,
+            typewriter_pause: 0.2,
+        }
+    }
+}
+
+/// How new text appears. Disappearing always slides and fades.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum AnimationMode {
+    #[default]
+    SlideFade,
+    /// Types the text out one character at a time like a Deltarune text box.
+    /// The appear phase lasts as long as the typing, `appear_duration` is
+    /// ignored.
+    Typewriter,
+}
+
+impl AnimationMode {
+    const ALL: [(Self, &'static str); 2] = [(Self::SlideFade, "slide_fade"), (Self::Typewriter, "typewriter")];
+}
+
+/// Curve for the slide of a transition. Appearing follows it as an ease-out,
+/// disappearing plays it backwards so the text speeds up as it leaves.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum Easing {
+    Linear,
+    #[default]
+    Quadratic,
+    Cubic,
+    /// Overshoots the resting position a little and comes back.
+    Back,
+    /// Springs around the resting position before settling.
+    Elastic,
+    /// Moves in a few hard jumps, like an old game would.
+    Steps,
+}
+
+impl Easing {
+    const ALL: [(Self, &'static str); 6] = [
+        (Self::Linear, "linear"),
+        (Self::Quadratic, "quadratic"),
+        (Self::Cubic, "cubic"),
+        (Self::Back, "back"),
+        (Self::Elastic, "elastic"),
+        (Self::Steps, "steps"),
+    ];
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum SlideDirection {
+    #[default]
+    Left,
+    Right,
+    Up,
+    Down,
+    /// Only fade.
+    None,
+}
+
+impl SlideDirection {
+    const ALL: [(Self, &'static str); 5] = [
+        (Self::Left, "left"),
+        (Self::Right, "right"),
+        (Self::Up, "up"),
+        (Self::Down, "down"),
+        (Self::None, "none"),
+    ];
+}
+
+impl Settings {
+    fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let data = fs::read_to_string(path)?;
+        let settings = serde_json::from_str(&data)?;
+        Ok(settings)
     }
 }
 
-fn status_rank(status: &MediaStatus) -> i32 {
-    match status {
-        MediaStatus::Playing => 3,
-        MediaStatus::Paused => 2,
-        MediaStatus::Stopped => 1,
+fn ensure_settings_parent(path: &Path) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
     }
+    Ok(())
+}
+
+struct SettingsState {
+    last_modified: Option<SystemTime>,
+    last_check: Instant,
 }
 
-fn is_better(candidate: &MediaInfo, current: &MediaInfo) -> bool {
-    status_rank(&candidate.status) > status_rank(&current.status)
+impl SettingsState {
+    fn new(path: &Path) -> Self {
+        let last_modified = fs::metadata(path).and_then(|meta| meta.modified()).ok();
+        Self {
+            last_modified,
+            last_check: Instant::now(),
+        }
+    }
 }
 
 fn start_tray(settings_path: PathBuf) {
@@ -633,16 +876,12 @@ fn tray_thread(settings_path: PathBuf) -> anyhow::Result<()> {
 }
 
 fn load_tray_icon() -> anyhow::Result<Icon> {
-    let icon_path = PathBuf::from("/usr/share/deltatune/deltatune.png");
-    let icon_path = if icon_path.exists() {
-        icon_path
-    } else {
-        PathBuf::from("assets/deltatune.png")
-    };
+    let icon = resolve_asset("deltatune.png").and_then(|asset| asset.read().ok());
 
-    let icon_image = image::open(&icon_path).map(|img| img.to_rgba8()).unwrap_or_else(|_| {
-        image::RgbaImage::from_pixel(32, 32, image::Rgba([255, 255, 255, 255]))
-    });
+    let icon_image = icon
+        .and_then(|icon| image::load_from_memory(&icon).ok())
+        .map(|img| img.to_rgba8())
+        .unwrap_or_else(|| image::RgbaImage::from_pixel(32, 32, image::Rgba([255, 255, 255, 255])));
 
     let icon_image = if icon_image.dimensions() != (32, 32) {
         image::imageops::resize(&icon_image, 32, 32, image::imageops::FilterType::Triangle)
@@ -654,7 +893,10 @@ fn load_tray_icon() -> anyhow::Result<Icon> {
 }
 
 fn build_settings_window(settings_path: PathBuf) -> anyhow::Result<gtk::Window> {
-    use gtk::{Adjustment, Box as GtkBox, Button, CheckButton, Label, Orientation, SpinButton, Window, WindowType};
+    use gtk::{
+        gdk, Adjustment, Box as GtkBox, Button, CheckButton, ColorButton, ComboBoxText, Label, Orientation,
+        SpinButton, Window, WindowType,
+    };
 
     let settings = Settings::load(&settings_path).unwrap_or_default();
 
@@ -691,6 +933,24 @@ fn build_settings_window(settings_path: PathBuf) -> anyhow::Result<gtk::Window>
     let (row_x_pos, spin_x_pos) = add_spin("X position", settings.x_pos as f32, -5000.0, 5000.0, 1.0);
     let (row_y_pos, spin_y_pos) = add_spin("Y position", settings.y_pos as f32, -5000.0, 5000.0, 1.0);
 
+    let row_anchor = GtkBox::new(Orientation::Horizontal, 8);
+    let lbl_anchor = Label::new(Some("Anchor"));
+    let combo_anchor = ComboBoxText::new();
+    for (anchor, id) in TextAnchor::ALL {
+        let label = match anchor {
+            TextAnchor::TopLeft => "Top left",
+            TextAnchor::TopRight => "Top right",
+            TextAnchor::BottomLeft => "Bottom left",
+            TextAnchor::BottomRight => "Bottom right",
+        };
+        combo_anchor.append(Some(id), label);
+        if anchor == settings.text_anchor {
+            combo_anchor.set_active_id(Some(id));
+        }
+    }
+    row_anchor.pack_start(&lbl_anchor, false, false, 0);
+    row_anchor.pack_end(&combo_anchor, false, false, 0);
+
     let check_artist = CheckButton::with_label("Show artist name");
     check_artist.set_active(settings.show_artist_name);
 
@@ -709,6 +969,9 @@ fn build_settings_window(settings_path: PathBuf) -> anyhow::Result<gtk::Window>
     let check_pin = CheckButton::with_label("Hyprland pin");
     check_pin.set_active(settings.hyprland_pin);
 
+    let check_hidpi = CheckButton::with_label("Follow monitor scaling (Wayland)");
+    check_hidpi.set_active(settings.hidpi_scaling);
+
     let hide_row = GtkBox::new(Orientation::Horizontal, 8);
     let check_hide = CheckButton::with_label("Hide automatically (seconds)");
     let hide_adj = Adjustment::new(settings.hide_automatically.unwrap_or(2.5) as f64, 0.5, 30.0, 0.5, 2.5, 0.0);
@@ -725,6 +988,181 @@ fn build_settings_window(settings_path: PathBuf) -> anyhow::Result<gtk::Window>
         spin_hide.set_sensitive(toggle.is_active());
     }));
 
+    let max_width_row = GtkBox::new(Orientation::Horizontal, 8);
+    let check_max_width = CheckButton::with_label("Maximum width (pixels)");
+    let max_width_adj = Adjustment::new(settings.max_width.unwrap_or(800) as f64, 50.0, 10000.0, 10.0, 100.0, 0.0);
+    let spin_max_width = SpinButton::new(Some(&max_width_adj), 1.0, 0);
+    if settings.max_width.is_some() {
+        check_max_width.set_active(true);
+    } else {
+        spin_max_width.set_sensitive(false);
+    }
+    max_width_row.pack_start(&check_max_width, false, false, 0);
+    max_width_row.pack_end(&spin_max_width, false, false, 0);
+
+    check_max_width.connect_toggled(glib::clone!(@weak spin_max_width => move |toggle| {
+        spin_max_width.set_sensitive(toggle.is_active());
+    }));
+
+    let row_overflow = GtkBox::new(Orientation::Horizontal, 8);
+    let lbl_overflow = Label::new(Some("Long lines"));
+    let combo_overflow = ComboBoxText::new();
+    for (overflow, id) in OverflowMode::ALL {
+        let label = match overflow {
+            OverflowMode::Marquee => "Scroll",
+            OverflowMode::Truncate => "Truncate",
+            OverflowMode::Wrap => "Wrap",
+        };
+        combo_overflow.append(Some(id), label);
+        if overflow == settings.overflow {
+            combo_overflow.set_active_id(Some(id));
+        }
+    }
+    row_overflow.pack_start(&lbl_overflow, false, false, 0);
+    row_overflow.pack_end(&combo_overflow, false, false, 0);
+
+    let (row_title_max_width, spin_title_max_width) = add_spin(
+        "Title width (0 = maximum width)",
+        settings.title_max_width.unwrap_or(0) as f32,
+        0.0,
+        10000.0,
+        10.0,
+    );
+    let (row_artist_max_width, spin_artist_max_width) = add_spin(
+        "Artist width (0 = maximum width)",
+        settings.artist_max_width.unwrap_or(0) as f32,
+        0.0,
+        10000.0,
+        10.0,
+    );
+    let (row_title_max_lines, spin_title_max_lines) =
+        add_spin("Title lines when wrapping", settings.title_max_lines as f32, 1.0, 10.0, 1.0);
+    let (row_artist_max_lines, spin_artist_max_lines) =
+        add_spin("Artist lines when wrapping", settings.artist_max_lines as f32, 1.0, 10.0, 1.0);
+
+    let (row_marquee_speed, spin_marquee_speed) =
+        add_spin("Marquee speed (pixels/s)", settings.marquee_speed, 0.0, 1000.0, 5.0);
+    let (row_marquee_pause, spin_marquee_pause) =
+        add_spin("Marquee pause (seconds)", settings.marquee_pause, 0.0, 30.0, 0.5);
+
+    let animation = settings.animation;
+    let lbl_animation = Label::new(Some("Animation"));
+    lbl_animation.set_halign(gtk::Align::Start);
+    let (row_appear_delay, spin_appear_delay) =
+        add_spin("Appear delay (seconds)", animation.appear_delay, 0.0, 5.0, 0.05);
+    let (row_appear_duration, spin_appear_duration) =
+        add_spin("Appear duration (seconds)", animation.appear_duration, 0.0, 5.0, 0.05);
+    let (row_disappear_duration, spin_disappear_duration) =
+        add_spin("Disappear duration (seconds)", animation.disappear_duration, 0.0, 5.0, 0.05);
+    let (row_stay_time, spin_stay_time) = add_spin("Stay time (seconds)", animation.stay_time, 0.0, 30.0, 0.5);
+    let (row_slide_in, spin_slide_in) = add_spin("Slide in distance", animation.slide_in_distance, -500.0, 500.0, 1.0);
+    let (row_slide_out, spin_slide_out) =
+        add_spin("Slide out distance", animation.slide_out_distance, -500.0, 500.0, 1.0);
+
+    let add_combo = |label: &str, items: &[(&str, &str)], active: &str| -> (GtkBox, ComboBoxText) {
+        let row = GtkBox::new(Orientation::Horizontal, 8);
+        let lbl = Label::new(Some(label));
+        let combo = ComboBoxText::new();
+        for (id, text) in items {
+            combo.append(Some(id), text);
+        }
+        combo.set_active_id(Some(active));
+        row.pack_start(&lbl, false, false, 0);
+        row.pack_end(&combo, false, false, 0);
+        (row, combo)
+    };
+    let easing_items = Easing::ALL.map(|(easing, id)| {
+        let label = match easing {
+            Easing::Linear => "Linear",
+            Easing::Quadratic => "Quadratic",
+            Easing::Cubic => "Cubic",
+            Easing::Back => "Back",
+            Easing::Elastic => "Elastic",
+            Easing::Steps => "Steps",
+        };
+        (id, label)
+    });
+    let direction_items = SlideDirection::ALL.map(|(direction, id)| {
+        let label = match direction {
+            SlideDirection::Left => "Left",
+            SlideDirection::Right => "Right",
+            SlideDirection::Up => "Up",
+            SlideDirection::Down => "Down",
+            SlideDirection::None => "None",
+        };
+        (id, label)
+    });
+    let mode_items = AnimationMode::ALL.map(|(mode, id)| {
+        let label = match mode {
+            AnimationMode::SlideFade => "Slide and fade",
+            AnimationMode::Typewriter => "Typewriter",
+        };
+        (id, label)
+    });
+    let mode_id = |mode: AnimationMode| AnimationMode::ALL.into_iter().find(|(m, _)| *m == mode).map_or("", |(_, id)| id);
+    let easing_id = |easing: Easing| Easing::ALL.into_iter().find(|(e, _)| *e == easing).map_or("", |(_, id)| id);
+    let direction_id = |direction: SlideDirection| {
+        SlideDirection::ALL
+            .into_iter()
+            .find(|(d, _)| *d == direction)
+            .map_or("", |(_, id)| id)
+    };
+    let (row_mode, combo_mode) = add_combo("Appear mode", &mode_items, mode_id(animation.mode));
+    let (row_typewriter_speed, spin_typewriter_speed) =
+        add_spin("Typewriter speed (chars/s)", animation.typewriter_speed, 0.0, 200.0, 1.0);
+    let (row_typewriter_pause, spin_typewriter_pause) =
+        add_spin("Typewriter pause (seconds)", animation.typewriter_pause, 0.0, 2.0, 0.05);
+    let (row_appear_easing, combo_appear_easing) =
+        add_combo("Appear easing", &easing_items, easing_id(animation.appear_easing));
+    let (row_disappear_easing, combo_disappear_easing) =
+        add_combo("Disappear easing", &easing_items, easing_id(animation.disappear_easing));
+    let (row_appear_direction, combo_appear_direction) =
+        add_combo("Appear direction", &direction_items, direction_id(animation.appear_direction));
+    let (row_disappear_direction, combo_disappear_direction) =
+        add_combo("Disappear direction", &direction_items, direction_id(animation.disappear_direction));
+
+    let add_color = |label: &str, color: Color| -> (GtkBox, ColorButton) {
+        let row = GtkBox::new(Orientation::Horizontal, 8);
+        let lbl = Label::new(Some(label));
+        let [r, g, b] = color.to_f32();
+        let button = ColorButton::with_rgba(&gdk::RGBA::new(r as f64, g as f64, b as f64, 1.0));
+        row.pack_start(&lbl, false, false, 0);
+        row.pack_end(&button, false, false, 0);
+        (row, button)
+    };
+    let title_style = settings.title_style;
+    let artist_style = settings.artist_style;
+    let lbl_style = Label::new(Some("Text style"));
+    lbl_style.set_halign(gtk::Align::Start);
+    let (row_title_color, color_title) = add_color("Title color", title_style.color);
+    let (row_artist_color, color_artist) = add_color("Artist color", artist_style.color);
+    let (row_artist_scale, spin_artist_scale) = add_spin("Artist size", artist_style.scale, 0.25, 4.0, 0.25);
+    let (row_artist_opacity, spin_artist_opacity) =
+        add_spin("Artist opacity", artist_style.opacity, 0.0, 1.0, 0.05);
+
+    // The window sets the outline and shadow of both lines at once.
+    let outline_row = GtkBox::new(Orientation::Horizontal, 8);
+    let check_outline = CheckButton::with_label("Outline");
+    check_outline.set_active(title_style.outline.is_some());
+    let [r, g, b] = title_style.outline.unwrap_or_default().color.to_f32();
+    let color_outline = ColorButton::with_rgba(&gdk::RGBA::new(r as f64, g as f64, b as f64, 1.0));
+    color_outline.set_sensitive(check_outline.is_active());
+    outline_row.pack_start(&check_outline, false, false, 0);
+    outline_row.pack_end(&color_outline, false, false, 0);
+
+    check_outline.connect_toggled(glib::clone!(@weak color_outline => move |toggle| {
+        color_outline.set_sensitive(toggle.is_active());
+    }));
+
+    let check_shadow = CheckButton::with_label("Drop shadow");
+    check_shadow.set_active(title_style.shadow.is_some());
+
+    let check_panel = CheckButton::with_label("Dialog box behind text");
+    check_panel.set_active(settings.panel.is_some());
+
+    let check_album_art = CheckButton::with_label("Show album art");
+    check_album_art.set_active(settings.album_art.is_some());
+
     let buttons = GtkBox::new(Orientation::Horizontal, 8);
     buttons.set_halign(gtk::Align::End);
     let save_button = Button::with_label("Save");
@@ -738,39 +1176,142 @@ fn build_settings_window(settings_path: PathBuf) -> anyhow::Result<gtk::Window>
     vbox.pack_start(&row_text_scale, false, false, 0);
     vbox.pack_start(&row_x_pos, false, false, 0);
     vbox.pack_start(&row_y_pos, false, false, 0);
+    vbox.pack_start(&row_anchor, false, false, 0);
     vbox.pack_start(&check_artist, false, false, 0);
     vbox.pack_start(&check_status, false, false, 0);
     vbox.pack_start(&check_debug, false, false, 0);
     vbox.pack_start(&check_force_opaque, false, false, 0);
     vbox.pack_start(&row_bg_opacity, false, false, 0);
     vbox.pack_start(&check_pin, false, false, 0);
+    vbox.pack_start(&check_hidpi, false, false, 0);
     vbox.pack_start(&hide_row, false, false, 0);
+    vbox.pack_start(&max_width_row, false, false, 0);
+    vbox.pack_start(&row_overflow, false, false, 0);
+    vbox.pack_start(&row_title_max_width, false, false, 0);
+    vbox.pack_start(&row_artist_max_width, false, false, 0);
+    vbox.pack_start(&row_title_max_lines, false, false, 0);
+    vbox.pack_start(&row_artist_max_lines, false, false, 0);
+    vbox.pack_start(&row_marquee_speed, false, false, 0);
+    vbox.pack_start(&row_marquee_pause, false, false, 0);
+    vbox.pack_start(&lbl_animation, false, false, 0);
+    vbox.pack_start(&row_appear_delay, false, false, 0);
+    vbox.pack_start(&row_appear_duration, false, false, 0);
+    vbox.pack_start(&row_disappear_duration, false, false, 0);
+    vbox.pack_start(&row_stay_time, false, false, 0);
+    vbox.pack_start(&row_slide_in, false, false, 0);
+    vbox.pack_start(&row_slide_out, false, false, 0);
+    vbox.pack_start(&row_mode, false, false, 0);
+    vbox.pack_start(&row_typewriter_speed, false, false, 0);
+    vbox.pack_start(&row_typewriter_pause, false, false, 0);
+    vbox.pack_start(&row_appear_easing, false, false, 0);
+    vbox.pack_start(&row_disappear_easing, false, false, 0);
+    vbox.pack_start(&row_appear_direction, false, false, 0);
+    vbox.pack_start(&row_disappear_direction, false, false, 0);
+    vbox.pack_start(&lbl_style, false, false, 0);
+    vbox.pack_start(&row_title_color, false, false, 0);
+    vbox.pack_start(&row_artist_color, false, false, 0);
+    vbox.pack_start(&row_artist_scale, false, false, 0);
+    vbox.pack_start(&row_artist_opacity, false, false, 0);
+    vbox.pack_start(&outline_row, false, false, 0);
+    vbox.pack_start(&check_shadow, false, false, 0);
+    vbox.pack_start(&check_panel, false, false, 0);
+    vbox.pack_start(&check_album_art, false, false, 0);
     vbox.pack_start(&buttons, false, false, 0);
 
     window.add(&vbox);
 
+    let combo_easing = |combo: &ComboBoxText| {
+        combo
+            .active_id()
+            .and_then(|id| Easing::ALL.into_iter().find(|(_, name)| *name == id.as_str()))
+            .map(|(easing, _)| easing)
+            .unwrap_or_default()
+    };
+    let combo_direction = |combo: &ComboBoxText| {
+        combo
+            .active_id()
+            .and_then(|id| SlideDirection::ALL.into_iter().find(|(_, name)| *name == id.as_str()))
+            .map(|(direction, _)| direction)
+            .unwrap_or_default()
+    };
+    let button_color = |button: &ColorButton| {
+        let rgba = button.rgba();
+        let channel = |c: f64| (c * 255.0).round().clamp(0.0, 255.0) as u8;
+        Color {
+            r: channel(rgba.red()),
+            g: channel(rgba.green()),
+            b: channel(rgba.blue()),
+        }
+    };
+
     let settings_path_for_save = settings_path.clone();
     save_button.connect_clicked(glib::clone!(@weak window,
         @weak spin_scale_factor,
         @weak spin_scale_x,
         @weak spin_scale_y,
         @weak spin_text_scale,
+        @weak combo_anchor,
         @weak check_artist,
         @weak check_status,
         @weak check_debug,
         @weak check_force_opaque,
         @weak spin_bg_opacity,
         @weak check_pin,
+        @weak check_hidpi,
         @weak check_hide,
-        @weak spin_hide
+        @weak spin_hide,
+        @weak check_max_width,
+        @weak spin_max_width,
+        @weak combo_overflow,
+        @weak spin_title_max_width,
+        @weak spin_artist_max_width,
+        @weak spin_title_max_lines,
+        @weak spin_artist_max_lines,
+        @weak spin_marquee_speed,
+        @weak spin_marquee_pause,
+        @weak spin_appear_delay,
+        @weak spin_appear_duration,
+        @weak spin_disappear_duration,
+        @weak spin_stay_time,
+        @weak spin_slide_in,
+        @weak spin_slide_out,
+        @weak combo_mode,
+        @weak spin_typewriter_speed,
+        @weak spin_typewriter_pause,
+        @weak combo_appear_easing,
+        @weak combo_disappear_easing,
+        @weak combo_appear_direction,
+        @weak combo_disappear_direction,
+        @weak color_title,
+        @weak color_artist,
+        @weak spin_artist_scale,
+        @weak spin_artist_opacity,
+        @weak check_outline,
+        @weak color_outline,
+        @weak check_shadow,
+        @weak check_panel,
+        @weak check_album_art
         => move |_| {
+            let outline = |style: &TextStyle| {
+                check_outline.is_active().then(|| Outline {
+                    color: button_color(&color_outline),
+                    ..style.outline.unwrap_or_default()
+                })
+            };
+            let shadow = |style: &TextStyle| check_shadow.is_active().then(|| style.shadow.unwrap_or_default());
             let new_settings = Settings {
                 scale_factor: spin_scale_factor.value() as f32,
                 scale_x: spin_scale_x.value() as f32,
                 scale_y: spin_scale_y.value() as f32,
                 text_scale: spin_text_scale.value() as f32,
+                hidpi_scaling: check_hidpi.is_active(),
                 x_pos: spin_x_pos.value() as i32,
                 y_pos: spin_y_pos.value() as i32,
+                text_anchor: combo_anchor
+                    .active_id()
+                    .and_then(|id| TextAnchor::ALL.into_iter().find(|(_, name)| *name == id.as_str()))
+                    .map(|(anchor, _)| anchor)
+                    .unwrap_or_default(),
                 show_artist_name: check_artist.is_active(),
                 show_playback_status: check_status.is_active(),
                 show_debug_overlay: check_debug.is_active(),
@@ -782,6 +1323,62 @@ fn build_settings_window(settings_path: PathBuf) -> anyhow::Result<gtk::Window>
                 } else {
                     None
                 },
+                max_width: if check_max_width.is_active() {
+                    Some(spin_max_width.value() as u32)
+                } else {
+                    None
+                },
+                overflow: combo_overflow
+                    .active_id()
+                    .and_then(|id| OverflowMode::ALL.into_iter().find(|(_, name)| *name == id.as_str()))
+                    .map(|(overflow, _)| overflow)
+                    .unwrap_or_default(),
+                title_max_width: Some(spin_title_max_width.value() as u32).filter(|&width| width > 0),
+                artist_max_width: Some(spin_artist_max_width.value() as u32).filter(|&width| width > 0),
+                title_max_lines: spin_title_max_lines.value() as u32,
+                artist_max_lines: spin_artist_max_lines.value() as u32,
+                marquee_speed: spin_marquee_speed.value() as f32,
+                marquee_pause: spin_marquee_pause.value() as f32,
+                animation: AnimationSettings {
+                    mode: combo_mode
+                        .active_id()
+                        .and_then(|id| AnimationMode::ALL.into_iter().find(|(_, name)| *name == id.as_str()))
+                        .map(|(mode, _)| mode)
+                        .unwrap_or_default(),
+                    appear_delay: spin_appear_delay.value() as f32,
+                    appear_duration: spin_appear_duration.value() as f32,
+                    disappear_duration: spin_disappear_duration.value() as f32,
+                    stay_time: spin_stay_time.value() as f32,
+                    slide_in_distance: spin_slide_in.value() as f32,
+                    slide_out_distance: spin_slide_out.value() as f32,
+                    appear_easing: combo_easing(&combo_appear_easing),
+                    disappear_easing: combo_easing(&combo_disappear_easing),
+                    appear_direction: combo_direction(&combo_appear_direction),
+                    disappear_direction: combo_direction(&combo_disappear_direction),
+                    typewriter_speed: spin_typewriter_speed.value() as f32,
+                    typewriter_pause: spin_typewriter_pause.value() as f32,
+                },
+                title_style: TextStyle {
+                    color: button_color(&color_title),
+                    outline: outline(&title_style),
+                    shadow: shadow(&title_style),
+                    ..title_style
+                },
+                artist_style: TextStyle {
+                    color: button_color(&color_artist),
+                    opacity: spin_artist_opacity.value() as f32,
+                    scale: spin_artist_scale.value() as f32,
+                    outline: outline(&artist_style),
+                    shadow: shadow(&artist_style),
+                    ..artist_style
+                },
+                panel: check_panel
+                    .is_active()
+                    .then(|| settings.panel.clone().unwrap_or_default()),
+                album_art: check_album_art
+                    .is_active()
+                    .then(|| settings.album_art.unwrap_or_default()),
+                ..Settings::load(&settings_path_for_save).unwrap_or_default()
             };
 
             match serde_json::to_string_pretty(&new_settings) {
@@ -825,6 +1422,8 @@ struct BitmapFont {
     glyphs: HashMap<u32, Glyph>,
     page_files: HashMap<u32, String>,
     space_advance: f32,
+    /// Extra advance between two codepoints, from `kerning` lines.
+    kerning: HashMap<(u32, u32), f32>,
 }
 
 impl BitmapFont {
@@ -836,33 +1435,85 @@ impl BitmapFont {
             glyphs: HashMap::new(),
             page_files: HashMap::new(),
             space_advance: 8.0,
+            kerning: HashMap::new(),
         }
     }
 
+    fn kerning(&self, first: char, second: char) -> f32 {
+        self.kerning
+            .get(&(first as u32, second as u32))
+            .copied()
+            .unwrap_or(0.0)
+    }
+
     fn set_texture_size(&mut self, width: f32, height: f32) {
         self.texture_width = width;
         self.texture_height = height;
     }
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
 enum TextAnchor {
+    #[default]
     TopLeft,
     TopRight,
     BottomLeft,
     BottomRight,
 }
 
-impl Default for TextAnchor {
-    fn default() -> Self {
-        Self::TopLeft
+impl TextAnchor {
+    const ALL: [(Self, &'static str); 4] = [
+        (Self::TopLeft, "top_left"),
+        (Self::TopRight, "top_right"),
+        (Self::BottomLeft, "bottom_left"),
+        (Self::BottomRight, "bottom_right"),
+    ];
+
+    fn is_right(self) -> bool {
+        matches!(self, Self::TopRight | Self::BottomRight)
     }
-}
 
-struct FontAtlas {
-    pages: HashMap<u32, FontAtlasPage>,
-}
+    fn is_bottom(self) -> bool {
+        matches!(self, Self::BottomLeft | Self::BottomRight)
+    }
+
+    fn layer_anchor(self) -> Anchor {
+        let vertical = if self.is_bottom() { Anchor::BOTTOM } else { Anchor::TOP };
+        let horizontal = if self.is_right() { Anchor::RIGHT } else { Anchor::LEFT };
+        vertical | horizontal
+    }
+
+    fn text_align(self) -> TextAlign {
+        if self.is_right() {
+            TextAlign::Right
+        } else {
+            TextAlign::Left
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TextAlign {
+    Left,
+    Right,
+}
+
+/// Layer-shell margins as (top, right, bottom, left); `x_pos`/`y_pos` are
+/// measured from whichever edges the text is anchored to.
+fn layer_margins(settings: &Settings) -> (i32, i32, i32, i32) {
+    let (x, y) = (settings.x_pos, settings.y_pos);
+    match settings.text_anchor {
+        TextAnchor::TopLeft => (y, 0, 0, x),
+        TextAnchor::TopRight => (y, x, 0, 0),
+        TextAnchor::BottomLeft => (0, 0, y, x),
+        TextAnchor::BottomRight => (0, x, y, 0),
+    }
+}
+
+struct FontAtlas {
+    pages: HashMap<u32, FontAtlasPage>,
+}
 
 struct FontAtlasPage {
     pixels: Vec<u8>,
@@ -871,33 +1522,35 @@ struct FontAtlasPage {
 }
 
 impl FontAtlas {
-    fn load(font_path: &Path, fallback_path: &Path, font: &mut BitmapFont) -> Result<Self> {
-        let mut page_sources: Vec<(u32, PathBuf)> = font
+    /// Loads the pages `font` lists from next to `font_asset`, or the PNG with
+    /// the same name if it lists none.
+    fn load(font_asset: &Asset, font: &mut BitmapFont) -> Result<Self> {
+        let mut page_sources: Vec<(u32, String)> = font
             .page_files
             .iter()
-            .map(|(id, file)| {
-                let path = font_path
-                    .parent()
-                    .unwrap_or_else(|| Path::new("."))
-                    .join(file);
-                (*id, path)
-            })
+            .map(|(id, file)| (*id, file.clone()))
             .collect();
         page_sources.sort_by_key(|(id, _)| *id);
 
         if page_sources.is_empty() {
-            page_sources.push((0, fallback_path.to_path_buf()));
+            let stem = font_asset.file_stem().unwrap_or("MusicTitleFont");
+            page_sources.push((0, format!("{stem}.png")));
         }
 
         let mut pages = HashMap::new();
-        for (id, page_path) in page_sources {
-            let image = match image::open(&page_path) {
+        for (id, file) in page_sources {
+            let Some(page_asset) = font_asset.sibling(&file) else {
+                eprintln!("Failed to load font atlas page {id}: {file} not found next to {font_asset}");
+                continue;
+            };
+            let image = match page_asset
+                .read()
+                .map_err(anyhow::Error::from)
+                .and_then(|data| Ok(image::load_from_memory(&data)?))
+            {
                 Ok(image) => image.to_rgba8(),
                 Err(err) => {
-                    eprintln!(
-                        "Failed to load font atlas page {id} from {}: {err}",
-                        page_path.display()
-                    );
+                    eprintln!("Failed to load font atlas page {id} from {page_asset}: {err}");
                     continue;
                 }
             };
@@ -951,803 +1604,39 @@ impl FontAtlas {
     }
 }
 
-fn load_bitmap_font(path: &Path) -> Result<BitmapFont> {
-    let content = fs::read_to_string(path)?;
-    let mut line_height = 48.0;
-    let mut tex_w = 512.0;
-    let mut tex_h = 512.0;
-    let mut glyphs = HashMap::new();
-    let mut page_files = HashMap::new();
-
-    for line in content.lines() {
-        if line.starts_with("common ") {
-            let values = parse_kv(line);
-            if let Some(value) = values.get("lineHeight") {
-                line_height = value.parse::<f32>().unwrap_or(line_height);
-            }
-            if let Some(value) = values.get("scaleW") {
-                tex_w = value.parse::<f32>().unwrap_or(tex_w);
-            }
-            if let Some(value) = values.get("scaleH") {
-                tex_h = value.parse::<f32>().unwrap_or(tex_h);
-            }
-        } else if line.starts_with("page ") {
-            let values = parse_kv(line);
-            let page_id = values
-                .get("id")
-                .and_then(|v| v.parse::<u32>().ok())
-                .unwrap_or(0);
-            if let Some(file) = values.get("file") {
-                page_files.insert(page_id, (*file).to_string());
-            }
-        } else if line.starts_with("char ") {
-            let values = parse_kv(line);
-            let id = values
-                .get("id")
-                .and_then(|v| v.parse::<u32>().ok())
-                .unwrap_or(0);
-            let glyph = Glyph {
-                x: values.get("x").and_then(|v| v.parse::<f32>().ok()).unwrap_or(0.0),
-                y: values.get("y").and_then(|v| v.parse::<f32>().ok()).unwrap_or(0.0),
-                width: values
-                    .get("width")
-                    .and_then(|v| v.parse::<f32>().ok())
-                    .unwrap_or(0.0),
-                height: values
-                    .get("height")
-                    .and_then(|v| v.parse::<f32>().ok())
-                    .unwrap_or(0.0),
-                x_offset: values
-                    .get("xoffset")
-                    .and_then(|v| v.parse::<f32>().ok())
-                    .unwrap_or(0.0),
-                y_offset: values
-                    .get("yoffset")
-                    .and_then(|v| v.parse::<f32>().ok())
-                    .unwrap_or(0.0),
-                x_advance: values
-                    .get("xadvance")
-                    .and_then(|v| v.parse::<f32>().ok())
-                    .unwrap_or(0.0),
-                page: values
-                    .get("page")
-                    .and_then(|v| v.parse::<u32>().ok())
-                    .unwrap_or(0),
-            };
-            glyphs.insert(id, glyph);
-        }
-    }
-
-    let space_advance = glyphs
-        .get(&32)
-        .map(|g| g.x_advance)
-        .unwrap_or(line_height * 0.25);
-
-    Ok(BitmapFont {
-        line_height,
-        texture_width: tex_w,
-        texture_height: tex_h,
-        glyphs,
-        page_files,
-        space_advance,
-    })
-}
-
-fn parse_kv(line: &str) -> HashMap<&str, &str> {
-    let mut map = HashMap::new();
-    for token in line.split_whitespace().skip(1) {
-        if let Some((key, value)) = token.split_once('=') {
-            map.insert(key, value.trim_matches('"'));
-        }
-    }
-    map
-}
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum DisplayState {
-    Hidden,
-    AppearingDelay,
-    Appearing,
-    Visible,
-    Disappearing,
-}
-
-struct DisplaySlot {
-    text: String,
-    state: DisplayState,
-    timer: f32,
-    opacity: f32,
-    offset_x: f32,
-}
-
-struct DisplayController {
-    slots: [DisplaySlot; 2],
-    primary_index: usize,
-    current_media: MediaInfo,
-}
-
-impl DisplayController {
-    fn new() -> Self {
-        Self {
-            slots: [
-                DisplaySlot {
-                    text: String::new(),
-                    state: DisplayState::Hidden,
-                    timer: 0.0,
-                    opacity: 0.0,
-                    offset_x: 0.0,
-                },
-                DisplaySlot {
-                    text: String::new(),
-                    state: DisplayState::Hidden,
-                    timer: 0.0,
-                    opacity: 0.0,
-                    offset_x: 0.0,
-                },
-            ],
-            primary_index: 0,
-            current_media: MediaInfo::default(),
-        }
-    }
-}
-
-struct X11App {
-    width: u32,
-    height: u32,
-    last_frame: Instant,
-    settings_path: PathBuf,
-    settings: Settings,
-    settings_state: SettingsState,
-    font: BitmapFont,
-    atlas: FontAtlas,
-    media: MediaState,
-    media_rx: Receiver<MediaInfo>,
-    display: DisplayController,
-    canvas: Vec<u8>,
-    pixels: Vec<u32>,
-}
-
-impl X11App {
-    fn new(
-        settings_path: PathBuf,
-        settings: Settings,
-        settings_state: SettingsState,
-        font: BitmapFont,
-        atlas: FontAtlas,
-        media_rx: Receiver<MediaInfo>,
-        window_width: u32,
-        window_height: u32,
-    ) -> Self {
-        Self {
-            width: window_width.max(1),
-            height: window_height.max(1),
-            last_frame: Instant::now(),
-            settings_path,
-            settings,
-            settings_state,
-            font,
-            atlas,
-            media: MediaState::default(),
-            media_rx,
-            display: DisplayController::new(),
-            canvas: Vec::new(),
-            pixels: Vec::new(),
-        }
-    }
-
-    fn draw(&mut self) {
-        let now = Instant::now();
-        let dt = now.duration_since(self.last_frame).as_secs_f32();
-        self.last_frame = now;
-
-        self.poll_media_updates();
-        self.reload_settings_if_needed();
-        self.update_display_state(dt);
-
-        let scale = self.settings.scale_factor * self.settings.text_scale;
-        let padding = 12.0;
-
-        let needed = (self.width * self.height * 4) as usize;
-        if self.canvas.len() != needed {
-            self.canvas.resize(needed, 0);
-        }
-
-        fill_background(
-            &mut self.canvas,
-            self.settings.force_opaque_background,
-            self.settings.background_opacity,
-        );
-
-        for slot in self.display.slots.iter() {
-            if slot.state == DisplayState::Hidden || slot.opacity <= 0.0 || slot.text.is_empty() {
-                continue;
-            }
-            let origin_x = padding + slot.offset_x;
-            let origin_y = padding;
-            draw_text(
-                &mut self.canvas,
-                self.width,
-                self.height,
-                &self.font,
-                &self.atlas,
-                &slot.text,
-                scale,
-                origin_x,
-                origin_y,
-                slot.opacity,
-            );
-        }
-
-        pack_bgra_to_argb(&self.canvas, &mut self.pixels);
-    }
-
-    fn poll_media_updates(&mut self) {
-        while let Ok(info) = self.media_rx.try_recv() {
-            self.media.info = info;
-            self.media.last_update = Instant::now();
-        }
-    }
-
-    fn reload_settings_if_needed(&mut self) {
-        if self.settings_state.last_check.elapsed() < Duration::from_millis(500) {
-            return;
-        }
-        self.settings_state.last_check = Instant::now();
-
-        let metadata = match fs::metadata(&self.settings_path) {
-            Ok(metadata) => metadata,
-            Err(_) => return,
-        };
-        let modified = metadata.modified().ok();
-        if modified.is_none() || modified == self.settings_state.last_modified {
-            return;
-        }
-
-        if let Ok(settings) = Settings::load(&self.settings_path) {
-            self.settings = settings;
-        }
-        self.settings_state.last_modified = modified;
-    }
-
-    fn update_display_state(&mut self, dt: f32) {
-        let mut title_changed = false;
-        let mut artist_changed = false;
-        let mut status_changed = false;
-
-        if self.display.current_media != self.media.info {
-            title_changed = self.display.current_media.title != self.media.info.title;
-            artist_changed = self.display.current_media.artist != self.media.info.artist;
-            status_changed = self.display.current_media.status != self.media.info.status;
-            self.display.current_media = self.media.info.clone();
-        }
-
-        let mut should_update = if self.settings.show_playback_status {
-            title_changed || artist_changed || status_changed
-        } else {
-            title_changed || artist_changed
-        };
-
-        if !should_update
-            && !self.settings.show_playback_status
-            && status_changed
-            && self.media.info.status == MediaStatus::Playing
-        {
-            should_update = true;
-        }
-
-        let primary_index = self.display.primary_index;
-        if !self.settings.show_playback_status
-            && should_update
-            && self.media.info.status != MediaStatus::Playing
-            && self.display.slots[primary_index].state == DisplayState::Hidden
-        {
-            should_update = false;
-        }
-
-        if should_update {
-            match self.display.slots[primary_index].state {
-                DisplayState::Hidden => swap_and_show(&mut self.display, &self.settings),
-                DisplayState::Visible => {
-                    if title_changed || artist_changed {
-                        swap_and_show(&mut self.display, &self.settings)
-                    }
-                }
-                DisplayState::Disappearing => swap_and_show(&mut self.display, &self.settings),
-                DisplayState::AppearingDelay | DisplayState::Appearing => {}
-            }
-        }
-
-        for slot in self.display.slots.iter_mut() {
-            update_display_slot(slot, &self.settings, &self.media, dt);
-        }
-    }
-}
-
-struct OverlayApp {
-    registry_state: RegistryState,
-    output_state: OutputState,
-    shm: Shm,
-    layer: LayerSurface,
-    pool: SlotPool,
-    width: u32,
-    height: u32,
-    exit: bool,
-    first_configure: bool,
-    last_frame: Instant,
-    settings_path: PathBuf,
-    settings: Settings,
-    settings_state: SettingsState,
-    font: BitmapFont,
-    atlas: FontAtlas,
-    media: MediaState,
-    media_rx: Receiver<MediaInfo>,
-    display: DisplayController,
-}
-
-impl OverlayApp {
-    fn draw(&mut self, qh: &QueueHandle<Self>) {
-        let now = Instant::now();
-        let dt = now.duration_since(self.last_frame).as_secs_f32();
-        self.last_frame = now;
-
-        self.poll_media_updates();
-        self.reload_settings_if_needed();
-        self.update_display_state(dt);
-
-        let scale = self.settings.scale_factor * self.settings.text_scale;
-        let padding = 12.0;
-
-        let mut max_width: f32 = 1.0;
-        let mut max_height: f32 = self.font.line_height * scale;
-        for slot in self.display.slots.iter() {
-            if slot.state == DisplayState::Hidden || slot.opacity <= 0.0 || slot.text.is_empty() {
-                continue;
-            }
-            let (w, h) = measure_text(&slot.text, &self.font, scale);
-            max_width = max_width.max(w);
-            max_height = max_height.max(h);
-        }
-
-        let desired_width = ((max_width + padding * 2.0) * self.settings.scale_x)
-            .max(1.0)
-            .round() as u32;
-        let desired_height = ((max_height + padding * 2.0) * self.settings.scale_y)
-            .max(1.0)
-            .round() as u32;
-
-        if desired_width != self.width || desired_height != self.height {
-            self.width = desired_width;
-            self.height = desired_height;
-            self.layer.set_size(self.width, self.height);
-        }
-
-        let stride = self.width as i32 * 4;
-        let (buffer, canvas) = self
-            .pool
-            .create_buffer(self.width as i32, self.height as i32, stride, wl_shm::Format::Argb8888)
-            .expect("create buffer");
-
-        fill_background(
-            canvas,
-            self.settings.force_opaque_background,
-            self.settings.background_opacity,
-        );
-
-        for slot in self.display.slots.iter() {
-            if slot.state == DisplayState::Hidden || slot.opacity <= 0.0 || slot.text.is_empty() {
-                continue;
-            }
-            let origin_x = padding + slot.offset_x;
-            let origin_y = padding;
-            draw_text(
-                canvas,
-                self.width,
-                self.height,
-                &self.font,
-                &self.atlas,
-                &slot.text,
-                scale,
-                origin_x,
-                origin_y,
-                slot.opacity,
-            );
-        }
-
-        self.layer
-            .wl_surface()
-            .damage_buffer(0, 0, self.width as i32, self.height as i32);
-        self.layer.wl_surface().frame(qh, self.layer.wl_surface().clone());
-        self.layer
-            .set_margin(self.settings.y_pos, 0, 0, self.settings.x_pos);
-        buffer.attach_to(self.layer.wl_surface()).expect("buffer attach");
-        self.layer.commit();
-    }
-
-    fn poll_media_updates(&mut self) {
-        while let Ok(info) = self.media_rx.try_recv() {
-            self.media.info = info;
-            self.media.last_update = Instant::now();
-        }
-    }
-
-    fn reload_settings_if_needed(&mut self) {
-        if self.settings_state.last_check.elapsed() < Duration::from_millis(500) {
-            return;
-        }
-        self.settings_state.last_check = Instant::now();
-
-        let metadata = match fs::metadata(&self.settings_path) {
-            Ok(metadata) => metadata,
-            Err(_) => return,
-        };
-        let modified = metadata.modified().ok();
-        if modified.is_none() || modified == self.settings_state.last_modified {
-            return;
-        }
-
-        if let Ok(settings) = Settings::load(&self.settings_path) {
-            self.settings = settings;
-        }
-        self.settings_state.last_modified = modified;
-    }
-
-    fn update_display_state(&mut self, dt: f32) {
-        let mut title_changed = false;
-        let mut artist_changed = false;
-        let mut status_changed = false;
-
-        if self.display.current_media != self.media.info {
-            title_changed = self.display.current_media.title != self.media.info.title;
-            artist_changed = self.display.current_media.artist != self.media.info.artist;
-            status_changed = self.display.current_media.status != self.media.info.status;
-            self.display.current_media = self.media.info.clone();
-        }
-
-        let mut should_update = if self.settings.show_playback_status {
-            title_changed || artist_changed || status_changed
-        } else {
-            title_changed || artist_changed
-        };
-
-        if !should_update
-            && !self.settings.show_playback_status
-            && status_changed
-            && self.media.info.status == MediaStatus::Playing
-        {
-            should_update = true;
-        }
-
-        let primary_index = self.display.primary_index;
-        if !self.settings.show_playback_status
-            && should_update
-            && self.media.info.status != MediaStatus::Playing
-            && self.display.slots[primary_index].state == DisplayState::Hidden
-        {
-            should_update = false;
-        }
-
-        if should_update {
-            match self.display.slots[primary_index].state {
-                DisplayState::Hidden => swap_and_show(&mut self.display, &self.settings),
-                DisplayState::Visible => {
-                    if title_changed || artist_changed {
-                        swap_and_show(&mut self.display, &self.settings)
-                    }
-                }
-                DisplayState::Disappearing => swap_and_show(&mut self.display, &self.settings),
-                DisplayState::AppearingDelay | DisplayState::Appearing => {}
-            }
-        }
-
-        for slot in self.display.slots.iter_mut() {
-            update_display_slot(slot, &self.settings, &self.media, dt);
-        }
-    }
-}
-
-impl CompositorHandler for OverlayApp {
-    fn scale_factor_changed(
-        &mut self,
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        _surface: &wl_surface::WlSurface,
-        _new_factor: i32,
-    ) {
-    }
-
-    fn transform_changed(
-        &mut self,
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        _surface: &wl_surface::WlSurface,
-        _new_transform: wl_output::Transform,
-    ) {
-    }
-
-    fn frame(
-        &mut self,
-        _conn: &Connection,
-        qh: &QueueHandle<Self>,
-        _surface: &wl_surface::WlSurface,
-        _time: u32,
-    ) {
-        self.draw(qh);
-    }
-
-    fn surface_enter(
-        &mut self,
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        _surface: &wl_surface::WlSurface,
-        _output: &wl_output::WlOutput,
-    ) {
-    }
-
-    fn surface_leave(
-        &mut self,
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        _surface: &wl_surface::WlSurface,
-        _output: &wl_output::WlOutput,
-    ) {
-    }
-}
-
-impl OutputHandler for OverlayApp {
-    fn output_state(&mut self) -> &mut OutputState {
-        &mut self.output_state
-    }
-
-    fn new_output(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _output: wl_output::WlOutput) {}
-    fn update_output(
-        &mut self,
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        _output: wl_output::WlOutput,
-    ) {
-    }
-    fn output_destroyed(
-        &mut self,
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        _output: wl_output::WlOutput,
-    ) {
-    }
-}
-
-impl ShmHandler for OverlayApp {
-    fn shm_state(&mut self) -> &mut Shm {
-        &mut self.shm
-    }
-}
-
-impl LayerShellHandler for OverlayApp {
-    fn closed(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _layer: &LayerSurface) {
-        self.exit = true;
-    }
-
-    fn configure(
-        &mut self,
-        _conn: &Connection,
-        qh: &QueueHandle<Self>,
-        _layer: &LayerSurface,
-        configure: LayerSurfaceConfigure,
-        _serial: u32,
-    ) {
-        if configure.new_size.0 != 0 && configure.new_size.1 != 0 {
-            self.width = configure.new_size.0;
-            self.height = configure.new_size.1;
-        }
-
-        if self.first_configure {
-            self.first_configure = false;
-            self.draw(qh);
-        }
-    }
-}
-
-impl ProvidesRegistryState for OverlayApp {
-    fn registry(&mut self) -> &mut RegistryState {
-        &mut self.registry_state
-    }
-    registry_handlers![OutputState];
-}
-
-delegate_compositor!(OverlayApp);
-delegate_output!(OverlayApp);
-
-delegate_shm!(OverlayApp);
-
-delegate_layer!(OverlayApp);
-
-delegate_registry!(OverlayApp);
-
-fn swap_and_show(controller: &mut DisplayController, settings: &Settings) {
-    let primary_index = controller.primary_index;
-    let secondary_index = 1 - primary_index;
-
-    controller.primary_index = secondary_index;
-    let new_primary = controller.primary_index;
-
-    let text = format_media_text(settings, &controller.current_media);
-    update_slot_text(&mut controller.slots[new_primary], text);
-
-    if controller.slots[secondary_index].state == DisplayState::Hidden {
-        controller.slots[new_primary].state = DisplayState::Appearing;
-        controller.slots[new_primary].timer = 0.0;
-    } else {
-        if controller.slots[secondary_index].state != DisplayState::Disappearing {
-            controller.slots[secondary_index].state = DisplayState::Disappearing;
-            controller.slots[secondary_index].timer = 0.0;
-        }
-        controller.slots[new_primary].state = DisplayState::AppearingDelay;
-        controller.slots[new_primary].timer = 0.0;
-    }
-}
-
-fn update_display_slot(slot: &mut DisplaySlot, settings: &Settings, media: &MediaState, dt: f32) {
-    const APPEAR_DELAY: f32 = 0.5;
-    const APPEAR_DURATION: f32 = 0.75;
-    const DISAPPEAR_DURATION: f32 = 0.75;
-    const STAY_TIME: f32 = 2.5;
-    const SLIDE_IN_DISTANCE: f32 = 24.0;
-    const SLIDE_OUT_DISTANCE: f32 = 24.0;
-
-    let scale = settings.scale_factor * settings.text_scale;
-    match slot.state {
-        DisplayState::AppearingDelay => {
-            if slot.timer == 0.0 {
-                slot.opacity = 0.0;
-                slot.offset_x = 0.0;
-            }
-            if slot.timer >= APPEAR_DELAY {
-                slot.state = DisplayState::Appearing;
-                slot.timer = 0.0;
-            }
-        }
-        DisplayState::Appearing => {
-            if slot.timer == 0.0 {
-                slot.opacity = 0.0;
-                slot.offset_x = 0.0;
-            }
-            let progress = (slot.timer / APPEAR_DURATION).clamp(0.0, 1.0);
-            slot.opacity = (progress * 1.5 - 0.25).clamp(0.0, 1.0);
-            slot.offset_x = interpolate_quadratic(SLIDE_IN_DISTANCE * scale, 0.0, progress);
-            if slot.timer >= APPEAR_DURATION {
-                slot.state = DisplayState::Visible;
-                slot.timer = 0.0;
-            }
-        }
-        DisplayState::Visible => {
-            if slot.timer == 0.0 {
-                slot.opacity = 1.0;
-                slot.offset_x = 0.0;
-            }
-            if let Some(hide_after) = settings.hide_automatically {
-                if slot.timer >= hide_after {
-                    slot.state = DisplayState::Disappearing;
-                    slot.timer = 0.0;
-                }
-            } else if !settings.show_playback_status
-                && slot.timer >= STAY_TIME
-                && (media.info.status == MediaStatus::Stopped || media.info.status == MediaStatus::Paused)
-            {
-                slot.state = DisplayState::Disappearing;
-                slot.timer = 0.0;
-            }
-        }
-        DisplayState::Disappearing => {
-            if slot.timer == 0.0 {
-                slot.opacity = 1.0;
-                slot.offset_x = 0.0;
-            }
-            let progress = (slot.timer / DISAPPEAR_DURATION).clamp(0.0, 1.0);
-            slot.opacity = ((1.0 - progress) * 1.5 - 0.25).clamp(0.0, 1.0);
-            slot.offset_x = interpolate_quadratic(-SLIDE_OUT_DISTANCE * scale, 0.0, 1.0 - progress);
-            if slot.timer >= DISAPPEAR_DURATION {
-                slot.state = DisplayState::Hidden;
-                slot.opacity = 0.0;
-            }
-        }
-        DisplayState::Hidden => {
-            slot.opacity = 0.0;
-            slot.offset_x = 0.0;
-        }
-    }
-
-    if slot.state != DisplayState::Hidden {
-        slot.timer += dt;
-    }
-}
-
-fn update_slot_text(slot: &mut DisplaySlot, text: String) {
-    if slot.text == text {
-        return;
-    }
-    slot.text = text;
-}
-
-fn interpolate_quadratic(a: f32, b: f32, t: f32) -> f32 {
-    let one_minus_t = 1.0 - t;
-    let progress = 1.0 - one_minus_t * one_minus_t;
-    a + (b - a) * progress
-}
-
-fn format_media_text(settings: &Settings, media: &MediaInfo) -> String {
-    if media.status == MediaStatus::Stopped {
-        return String::new();
-    }
-
-    let mut title = media.title.trim().to_string();
-    let mut artist = media.artist.trim().to_string();
-
-    if artist.ends_with(" - Topic") {
-        artist.truncate(artist.len().saturating_sub(8));
-    }
-
-    if !artist.is_empty() && !title.is_empty() {
-        let prefix = format!("{artist} - ");
-        if title.starts_with(&prefix) {
-            title = title.replacen(&prefix, "", 1);
-        }
-        let suffix = format!(" - {artist}");
-        if title.ends_with(&suffix) {
-            title.truncate(title.len().saturating_sub(suffix.len()));
+/// Which monitor(s) the Wayland overlay is shown on. A name or description that
+/// matches no output falls back to `focused`, or to `primary` on X11.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum OutputSelection {
+    /// Leave it to the compositor, which usually picks the focused output.
+    #[default]
+    Focused,
+    /// Mirror the overlay on every output.
+    All,
+    /// The primary monitor. Only X11 has a notion of one; on Wayland this
+    /// behaves like `focused`.
+    Primary,
+    /// Connector name, e.g. `DP-1`.
+    Name(String),
+    /// Any output whose description contains this text.
+    Description(String),
+}
+
+fn measure_line(line: &str, font: &BitmapFont, scale: f32) -> f32 {
+    let mut width = 0.0;
+    let mut previous = None;
+    for ch in line.chars() {
+        if let Some(previous) = previous {
+            width += font.kerning(previous, ch) * scale;
         }
-    }
-
-    let mut buffer = String::new();
-    if settings.show_playback_status {
-        let icon = match media.status {
-            MediaStatus::Playing => "♪",
-            MediaStatus::Paused => "⏸",
-            MediaStatus::Stopped => "",
+        width += match font.glyphs.get(&(ch as u32)) {
+            Some(glyph) => glyph.x_advance * scale,
+            None => font.space_advance * scale,
         };
-        if !icon.is_empty() {
-            buffer.push_str(icon);
-            buffer.push_str("~\u{2009}\u{2009}\u{2009}");
-        }
-    } else if media.status == MediaStatus::Playing {
-        buffer.push_str("♪~\u{2009}\u{2009}\u{2009}");
-    }
-
-    if !title.is_empty() {
-        buffer.push_str(&title);
-    }
-
-    if settings.show_artist_name && !artist.is_empty() {
-        if !buffer.is_empty() {
-            buffer.push('\n');
-        }
-        buffer.push_str(&artist);
+        previous = Some(ch);
     }
-
-    buffer
-}
-
-fn measure_text(text: &str, font: &BitmapFont, scale: f32) -> (f32, f32) {
-    let mut max_width: f32 = 0.0;
-    let mut current_width: f32 = 0.0;
-    let mut lines = 1;
-
-    for ch in text.chars() {
-        if ch == '\n' {
-            max_width = max_width.max(current_width);
-            current_width = 0.0;
-            lines += 1;
-            continue;
-        }
-
-        if let Some(glyph) = font.glyphs.get(&(ch as u32)) {
-            current_width += glyph.x_advance * scale;
-        } else {
-            current_width += font.space_advance * scale;
-        }
-    }
-
-    max_width = max_width.max(current_width);
-    let height = lines as f32 * font.line_height * scale;
-    (max_width, height)
+    width
 }
 
 fn fill_background(canvas: &mut [u8], force_opaque: bool, opacity: f32) {
@@ -1775,6 +1664,23 @@ fn pack_bgra_to_argb(src: &[u8], dst: &mut Vec<u32>) {
     }
 }
 
+/// The canvas is premultiplied BGRA, image files want straight RGBA.
+fn bgra_to_straight_rgba(canvas: &[u8]) -> Vec<u8> {
+    let mut rgba = Vec::with_capacity(canvas.len());
+    for chunk in canvas.chunks_exact(4) {
+        let a = chunk[3];
+        let unpremultiply = |c: u8| {
+            if a == 0 {
+                0
+            } else {
+                ((c as u32 * 255 + a as u32 / 2) / a as u32).min(255) as u8
+            }
+        };
+        rgba.extend_from_slice(&[unpremultiply(chunk[2]), unpremultiply(chunk[1]), unpremultiply(chunk[0]), a]);
+    }
+    rgba
+}
+
 fn draw_text(
     canvas: &mut [u8],
     canvas_w: u32,
@@ -1782,20 +1688,56 @@ fn draw_text(
     font: &BitmapFont,
     atlas: &FontAtlas,
     text: &str,
+    visible_glyphs: usize,
     scale: f32,
     origin_x: f32,
     origin_y: f32,
     opacity: f32,
+    style: &TextStyle,
+    align: TextAlign,
+    clip: Option<(f32, f32)>,
 ) {
-    let mut cursor_x = origin_x;
-    let mut cursor_y = origin_y;
+    const FADE_WIDTH: f32 = 6.0;
 
+    let line_bounds = |line: &str| {
+        let width = measure_line(line, font, scale);
+        match align {
+            TextAlign::Left => (origin_x, origin_x + width),
+            TextAlign::Right => (origin_x - width, origin_x),
+        }
+    };
+    let mut lines = text.split('\n');
+    let (mut line_left, mut line_right) = line_bounds(lines.next().unwrap_or(""));
+    let (mut text_left, mut text_right) = (line_left, line_right);
+    let mut cursor_x = line_left;
+    let mut cursor_y = origin_y;
+    let fade_width = FADE_WIDTH * scale;
+    let line_height = font.line_height * scale;
+    let opacity = opacity * style.opacity;
+
+    // Lines are still aligned by their full width, so text that is being
+    // typed out does not move around.
+    let mut placed = Vec::new();
+    let mut glyphs_left = visible_glyphs;
+    let mut previous = None;
     for ch in text.chars() {
         if ch == '\n' {
-            cursor_x = origin_x;
-            cursor_y += font.line_height * scale;
+            (line_left, line_right) = line_bounds(lines.next().unwrap_or(""));
+            text_left = text_left.min(line_left);
+            text_right = text_right.max(line_right);
+            cursor_x = line_left;
+            cursor_y += line_height;
+            previous = None;
             continue;
         }
+        if glyphs_left == 0 {
+            break;
+        }
+        glyphs_left -= 1;
+        if let Some(previous) = previous {
+            cursor_x += font.kerning(previous, ch) * scale;
+        }
+        previous = Some(ch);
 
         let glyph = match font.glyphs.get(&(ch as u32)) {
             Some(glyph) => glyph,
@@ -1804,36 +1746,60 @@ fn draw_text(
                 continue;
             }
         };
-        let page = match atlas.page(glyph.page) {
-            Some(page) => page,
-            None => {
-                cursor_x += glyph.x_advance * scale;
-                continue;
-            }
-        };
+        if let Some(page) = atlas.page(glyph.page) {
+            placed.push(PlacedGlyph {
+                glyph,
+                page,
+                x: (cursor_x + glyph.x_offset * scale).round() as i32,
+                y: (cursor_y + glyph.y_offset * scale).round() as i32,
+                width: (glyph.width * scale).round().max(1.0) as i32,
+                height: (glyph.height * scale).round().max(1.0) as i32,
+                line_top: cursor_y,
+            });
+        }
+        cursor_x += glyph.x_advance * scale;
+    }
 
-        let x0 = cursor_x + glyph.x_offset * scale;
-        let y0 = cursor_y + glyph.y_offset * scale;
-        let dest_w = (glyph.width * scale).round().max(1.0) as i32;
-        let dest_h = (glyph.height * scale).round().max(1.0) as i32;
+    // Everything is drawn into a layer first and then onto the canvas in one
+    // go, so the outline does not show through half transparent glyphs.
+    let margin = style.margin().scaled(scale);
+    let Some(layer_rect) = placed
+        .iter()
+        .map(|glyph| (glyph.x, glyph.y, glyph.x + glyph.width, glyph.y + glyph.height))
+        .reduce(|a, b| (a.0.min(b.0), a.1.min(b.1), a.2.max(b.2), a.3.max(b.3)))
+    else {
+        return;
+    };
+    let layer_x = (layer_rect.0 - margin.left.ceil() as i32).max(0);
+    let layer_y = (layer_rect.1 - margin.top.ceil() as i32).max(0);
+    let layer_w = (layer_rect.2 + margin.right.ceil() as i32).min(canvas_w as i32) - layer_x;
+    let layer_h = (layer_rect.3 + margin.bottom.ceil() as i32).min(canvas_h as i32) - layer_y;
+    if layer_w <= 0 || layer_h <= 0 {
+        return;
+    }
+    let mut layer = vec![0u8; (layer_w * layer_h * 4) as usize];
 
-        for dy in 0..dest_h {
+    for placed in &placed {
+        let glyph = placed.glyph;
+        let page = placed.page;
+        for dy in 0..placed.height {
             let src_y = ((dy as f32) / scale).floor() as i32;
             if src_y < 0 || src_y >= glyph.height as i32 {
                 continue;
             }
-            let dest_y = y0.round() as i32 + dy;
-            if dest_y < 0 || dest_y >= canvas_h as i32 {
+            let layer_row = placed.y + dy - layer_y;
+            if layer_row < 0 || layer_row >= layer_h {
                 continue;
             }
+            let tint = style.tint_at(((placed.y + dy) as f32 + 0.5 - placed.line_top) / line_height);
 
-            for dx in 0..dest_w {
+            for dx in 0..placed.width {
                 let src_x = ((dx as f32) / scale).floor() as i32;
                 if src_x < 0 || src_x >= glyph.width as i32 {
                     continue;
                 }
-                let dest_x = x0.round() as i32 + dx;
-                if dest_x < 0 || dest_x >= canvas_w as i32 {
+                let layer_column = placed.x + dx - layer_x;
+                if layer_column < 0 || layer_column >= layer_w {
                     continue;
                 }
 
@@ -1856,23 +1822,152 @@ fn draw_text(
                     continue;
                 }
 
-                let dst_index = ((dest_y as u32 * canvas_w + dest_x as u32) * 4) as usize;
-                blend_pixel(&mut canvas[dst_index..dst_index + 4], src_r, src_g, src_b, src_a, opacity);
+                let dst_index = ((layer_row * layer_w + layer_column) * 4) as usize;
+                blend_pixel(&mut layer[dst_index..dst_index + 4], src_r, src_g, src_b, src_a, tint, 1.0);
             }
         }
+    }
 
-        cursor_x += glyph.x_advance * scale;
+    draw_text_effects(&mut layer, layer_w, layer_h, style, scale);
+
+    for row in 0..layer_h {
+        let dest_y = layer_y + row;
+        for column in 0..layer_w {
+            let dest_x = layer_x + column;
+
+            // Outside the clip rectangle nothing is drawn, and edges that
+            // cut through the line fade out instead of ending hard.
+            let mut clip_opacity = 1.0;
+            if let Some((clip_left, clip_right)) = clip {
+                let x = dest_x as f32 + 0.5;
+                if x < clip_left || x > clip_right {
+                    continue;
+                }
+                if text_left < clip_left {
+                    clip_opacity = f32::min(clip_opacity, (x - clip_left) / fade_width);
+                }
+                if text_right > clip_right {
+                    clip_opacity = f32::min(clip_opacity, (clip_right - x) / fade_width);
+                }
+            }
+
+            let src_index = ((row * layer_w + column) * 4) as usize;
+            let src = &layer[src_index..src_index + 4];
+            if src[3] == 0 {
+                continue;
+            }
+            let dst_index = ((dest_y as u32 * canvas_w + dest_x as u32) * 4) as usize;
+            blend_premultiplied(&mut canvas[dst_index..dst_index + 4], src, opacity * clip_opacity);
+        }
+    }
+}
+
+/// A glyph `draw_text` has laid out, in device pixels.
+struct PlacedGlyph<'a> {
+    glyph: &'a Glyph,
+    page: &'a FontAtlasPage,
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+    line_top: f32,
+}
+
+/// Puts the outline and then the shadow of `style` under the glyphs in
+/// `layer`. Both are built from whole font pixels, so they line up with the
+/// scaled up glyphs.
+fn draw_text_effects(layer: &mut [u8], layer_w: i32, layer_h: i32, style: &TextStyle, scale: f32) {
+    if style.outline.is_none() && style.shadow.is_none() {
+        return;
+    }
+    let alpha_at = |mask: &[f32], x: i32, y: i32| {
+        if x < 0 || y < 0 || x >= layer_w || y >= layer_h {
+            0.0
+        } else {
+            mask[(y * layer_w + x) as usize]
+        }
+    };
+
+    let glyph_mask: Vec<f32> = layer.chunks_exact(4).map(|pixel| pixel[3] as f32 / 255.0).collect();
+    let outline_mask = style.outline.map(|outline| {
+        // A rounded square, so a one pixel outline includes the diagonals.
+        let width = outline.width as i32;
+        let offsets: Vec<(i32, i32)> = (-width..=width)
+            .flat_map(|dy| (-width..=width).map(move |dx| (dx, dy)))
+            .filter(|(dx, dy)| dx * dx + dy * dy <= width * width + width)
+            .map(|(dx, dy)| ((dx as f32 * scale).round() as i32, (dy as f32 * scale).round() as i32))
+            .collect();
+        let mut mask = vec![0.0; glyph_mask.len()];
+        for y in 0..layer_h {
+            for x in 0..layer_w {
+                mask[(y * layer_w + x) as usize] = offsets
+                    .iter()
+                    .map(|(dx, dy)| alpha_at(&glyph_mask, x - dx, y - dy))
+                    .fold(0.0, f32::max);
+            }
+        }
+        mask
+    });
+    let shape_mask = outline_mask.as_deref().unwrap_or(&glyph_mask);
+    let shadow_offset = style.shadow.map(|shadow| {
+        (
+            (shadow.offset_x as f32 * scale).round() as i32,
+            (shadow.offset_y as f32 * scale).round() as i32,
+        )
+    });
+
+    for y in 0..layer_h {
+        for x in 0..layer_w {
+            let index = (y * layer_w + x) as usize;
+            let mut under = [0.0f32; 4];
+            if let (Some(shadow), Some((dx, dy))) = (style.shadow, shadow_offset) {
+                let alpha = alpha_at(shape_mask, x - dx, y - dy) * shadow.opacity.clamp(0.0, 1.0);
+                let [r, g, b] = shadow.color.to_f32();
+                under = [b * alpha, g * alpha, r * alpha, alpha];
+            }
+            if let (Some(outline), Some(mask)) = (style.outline, outline_mask.as_deref()) {
+                let alpha = mask[index];
+                let [r, g, b] = outline.color.to_f32();
+                under = [
+                    b * alpha + under[0] * (1.0 - alpha),
+                    g * alpha + under[1] * (1.0 - alpha),
+                    r * alpha + under[2] * (1.0 - alpha),
+                    alpha + under[3] * (1.0 - alpha),
+                ];
+            }
+            if under[3] <= 0.0 {
+                continue;
+            }
+
+            let pixel = &mut layer[index * 4..index * 4 + 4];
+            let glyph_alpha = pixel[3] as f32 / 255.0;
+            for (channel, under) in pixel.iter_mut().zip(under) {
+                let value = *channel as f32 / 255.0 + under * (1.0 - glyph_alpha);
+                *channel = (value * 255.0).round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+}
+
+/// Draws a premultiplied BGRA pixel over another one.
+fn blend_premultiplied(dst: &mut [u8], src: &[u8], opacity: f32) {
+    let sa = src[3] as f32 / 255.0 * opacity;
+    for (dst, src) in dst.iter_mut().zip(src) {
+        let value = *src as f32 / 255.0 * opacity + *dst as f32 / 255.0 * (1.0 - sa);
+        *dst = (value * 255.0).round().clamp(0.0, 255.0) as u8;
     }
 }
 
-fn blend_pixel(dst: &mut [u8], src_r: u8, src_g: u8, src_b: u8, src_a: u8, opacity: f32) {
+/// Draws a straight alpha source pixel over a premultiplied BGRA one. `tint`
+/// is multiplied with the source color.
+fn blend_pixel(dst: &mut [u8], src_r: u8, src_g: u8, src_b: u8, src_a: u8, tint: [f32; 3], opacity: f32) {
     let sa = (src_a as f32 / 255.0) * opacity;
     if sa <= 0.0 {
         return;
     }
-    let sr = (src_r as f32 / 255.0) * sa;
-    let sg = (src_g as f32 / 255.0) * sa;
-    let sb = (src_b as f32 / 255.0) * sa;
+    let sr = (src_r as f32 / 255.0) * tint[0] * sa;
+    let sg = (src_g as f32 / 255.0) * tint[1] * sa;
+    let sb = (src_b as f32 / 255.0) * tint[2] * sa;
 
     let db = dst[0] as f32 / 255.0;
     let dg = dst[1] as f32 / 255.0;
@@ -0,0 +1,138 @@
+mod file;
+mod mpd;
+mod mpris_source;
+mod stdin;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+
+pub(crate) use file::FileSource;
+pub(crate) use mpd::MpdSource;
+pub(crate) use mpris_source::MprisSource;
+pub(crate) use stdin::StdinSource;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum MediaStatus {
+    Playing,
+    Paused,
+    Stopped,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct MediaInfo {
+    pub(crate) title: String,
+    pub(crate) artist: String,
+    pub(crate) status: MediaStatus,
+    /// Where the cover art is, as in `mpris:artUrl`. Empty if there is none.
+    pub(crate) art_url: String,
+}
+
+impl Default for MediaInfo {
+    fn default() -> Self {
+        Self {
+            title: String::new(),
+            artist: String::new(),
+            status: MediaStatus::Stopped,
+            art_url: String::new(),
+        }
+    }
+}
+
+/// Something that knows what is currently playing.
+///
+/// `run` blocks for as long as the source is alive and pushes every change
+/// through `tx`. Sources are expected to handle reconnects themselves and only
+/// return once there is nothing left to report.
+pub(crate) trait MediaSource: Send {
+    fn run(&mut self, tx: &Sender<MediaInfo>) -> Result<()>;
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum MediaSourceKind {
+    #[default]
+    Mpris,
+    Mpd,
+    Stdin,
+    File,
+}
+
+impl MediaSourceKind {
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        match value {
+            "mpris" => Some(Self::Mpris),
+            "mpd" => Some(Self::Mpd),
+            "stdin" => Some(Self::Stdin),
+            "file" => Some(Self::File),
+            _ => None,
+        }
+    }
+}
+
+pub(crate) struct MediaSourceOptions {
+    pub(crate) kind: MediaSourceKind,
+    pub(crate) settings_path: PathBuf,
+    pub(crate) mpris_bus_address: Option<String>,
+    pub(crate) mpd_address: String,
+    pub(crate) mpd_password: Option<String>,
+    pub(crate) media_file: Option<PathBuf>,
+}
+
+pub(crate) fn create_media_source(options: MediaSourceOptions) -> Result<Box<dyn MediaSource>> {
+    let source: Box<dyn MediaSource> = match options.kind {
+        MediaSourceKind::Mpris => Box::new(MprisSource::new(
+            options.mpris_bus_address,
+            options.settings_path,
+        )),
+        MediaSourceKind::Mpd => Box::new(MpdSource::new(options.mpd_address, options.mpd_password)),
+        MediaSourceKind::Stdin => Box::new(StdinSource),
+        MediaSourceKind::File => {
+            let path = options
+                .media_file
+                .ok_or_else(|| anyhow::anyhow!("the file media source needs media_file to be set"))?;
+            Box::new(FileSource::new(path))
+        }
+    };
+    Ok(source)
+}
+
+pub(crate) fn spawn_media_source(mut source: Box<dyn MediaSource>, tx: Sender<MediaInfo>) {
+    std::thread::spawn(move || {
+        if let Err(err) = source.run(&tx) {
+            eprintln!("Media source stopped: {err}");
+        }
+    });
+}
+
+#[derive(Debug, Deserialize)]
+struct MediaUpdate {
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    artist: String,
+    status: Option<MediaStatus>,
+    #[serde(default)]
+    art_url: String,
+}
+
+/// Parses the JSON object format shared by the stdin and file sources and the
+/// headless script, e.g.
+/// `{"title": "Field of Hopes and Dreams", "artist": "Toby Fox", "status": "playing"}`,
+/// optionally with an `art_url`. A missing status means playing as long as there is a title.
+pub(crate) fn parse_media_update(json: &str) -> Result<MediaInfo> {
+    let update: MediaUpdate = serde_json::from_str(json)?;
+    let status = update.status.unwrap_or(if update.title.is_empty() {
+        MediaStatus::Stopped
+    } else {
+        MediaStatus::Playing
+    });
+    Ok(MediaInfo {
+        title: update.title,
+        artist: update.artist,
+        status,
+        art_url: update.art_url,
+    })
+}
@@ -0,0 +1,78 @@
+use super::{parse_media_update, MediaInfo, MediaSource, MediaStatus};
+use anyhow::Result;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+use std::time::{Duration, SystemTime};
+
+const FILE_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Watches a file that some other program rewrites whenever the track changes.
+///
+/// The file either holds a JSON object in the same format as the stdin source
+/// or plain text with the title on the first line, the artist on the second
+/// and optionally the cover art URL on the third. An empty or missing file
+/// means nothing is playing.
+pub(crate) struct FileSource {
+    path: PathBuf,
+}
+
+impl FileSource {
+    pub(crate) fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    fn read(&self) -> Result<MediaInfo> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(MediaInfo::default()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let content = content.trim();
+        if content.starts_with('{') {
+            return parse_media_update(content);
+        }
+
+        let mut lines = content.lines();
+        let title = lines.next().unwrap_or("").trim().to_string();
+        let artist = lines.next().unwrap_or("").trim().to_string();
+        let art_url = lines.next().unwrap_or("").trim().to_string();
+        let status = if title.is_empty() {
+            MediaStatus::Stopped
+        } else {
+            MediaStatus::Playing
+        };
+        Ok(MediaInfo {
+            title,
+            artist,
+            status,
+            art_url,
+        })
+    }
+}
+
+impl MediaSource for FileSource {
+    fn run(&mut self, tx: &Sender<MediaInfo>) -> Result<()> {
+        let mut last_modified: Option<SystemTime> = None;
+        let mut last_sent = MediaInfo::default();
+        let mut first_read = true;
+        loop {
+            let modified = fs::metadata(&self.path).and_then(|meta| meta.modified()).ok();
+            if first_read || modified != last_modified {
+                first_read = false;
+                last_modified = modified;
+                match self.read() {
+                    Ok(next) => {
+                        if next != last_sent {
+                            let _ = tx.send(next.clone());
+                            last_sent = next;
+                        }
+                    }
+                    Err(err) => eprintln!("Failed to read {}: {err}", self.path.display()),
+                }
+            }
+            std::thread::sleep(FILE_POLL_INTERVAL);
+        }
+    }
+}
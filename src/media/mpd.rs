@@ -0,0 +1,340 @@
+use super::{MediaInfo, MediaSource, MediaStatus};
+use anyhow::{anyhow, Result};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+const MPD_RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+/// Talks the plain-text MPD protocol over TCP (`host:port`) or a unix socket
+/// (any address starting with `/`). Uses `idle player` so updates arrive as
+/// soon as mpd reports them.
+pub(crate) struct MpdSource {
+    address: String,
+    password: Option<String>,
+    connected: bool,
+}
+
+impl MpdSource {
+    pub(crate) fn new(address: String, password: Option<String>) -> Self {
+        Self {
+            address,
+            password,
+            connected: false,
+        }
+    }
+
+    fn session(&mut self, tx: &Sender<MediaInfo>, last_sent: &mut MediaInfo) -> Result<()> {
+        if self.address.starts_with('/') {
+            let stream = UnixStream::connect(&self.address)?;
+            let reader = BufReader::new(stream.try_clone()?);
+            self.run_session(reader, stream, tx, last_sent)
+        } else {
+            let stream = TcpStream::connect(&self.address)?;
+            let reader = BufReader::new(stream.try_clone()?);
+            self.run_session(reader, stream, tx, last_sent)
+        }
+    }
+
+    fn run_session<R: Read, W: Write>(
+        &mut self,
+        reader: BufReader<R>,
+        writer: W,
+        tx: &Sender<MediaInfo>,
+        last_sent: &mut MediaInfo,
+    ) -> Result<()> {
+        let mut connection = MpdConnection { reader, writer };
+
+        let greeting = connection.read_line()?;
+        if !greeting.starts_with("OK MPD ") {
+            return Err(anyhow!("unexpected mpd greeting: {greeting}"));
+        }
+        self.connected = true;
+
+        if let Some(password) = &self.password {
+            connection.command(&format!("password {}", quote_mpd_arg(password)))?;
+        }
+
+        loop {
+            let song = connection.command("currentsong")?;
+            let status = connection.command("status")?;
+            let next = media_info_from_mpd(&song, &status);
+            if next != *last_sent {
+                let _ = tx.send(next.clone());
+                *last_sent = next;
+            }
+
+            connection.command("idle player")?;
+        }
+    }
+}
+
+impl MediaSource for MpdSource {
+    fn run(&mut self, tx: &Sender<MediaInfo>) -> Result<()> {
+        let mut last_sent = MediaInfo::default();
+        let mut first_attempt = true;
+        loop {
+            if let Err(err) = self.session(tx, &mut last_sent) {
+                // Only complain once per outage instead of every reconnect attempt.
+                if self.connected || first_attempt {
+                    eprintln!("Lost connection to mpd at {}: {err}", self.address);
+                }
+            }
+            self.connected = false;
+            first_attempt = false;
+
+            if last_sent != MediaInfo::default() {
+                last_sent = MediaInfo::default();
+                let _ = tx.send(last_sent.clone());
+            }
+            std::thread::sleep(MPD_RECONNECT_DELAY);
+        }
+    }
+}
+
+struct MpdConnection<R, W> {
+    reader: BufReader<R>,
+    writer: W,
+}
+
+impl<R: Read, W: Write> MpdConnection<R, W> {
+    fn read_line(&mut self) -> Result<String> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(anyhow!("mpd closed the connection"));
+        }
+        Ok(line.trim_end_matches(['\r', '\n']).to_string())
+    }
+
+    /// Sends one command and collects the `key: value` pairs of its response.
+    fn command(&mut self, command: &str) -> Result<Vec<(String, String)>> {
+        self.writer.write_all(command.as_bytes())?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()?;
+
+        let mut pairs = Vec::new();
+        loop {
+            let line = self.read_line()?;
+            if line == "OK" {
+                return Ok(pairs);
+            }
+            if let Some(error) = line.strip_prefix("ACK ") {
+                return Err(anyhow!("mpd rejected {command:?}: {error}"));
+            }
+            if let Some((key, value)) = line.split_once(": ") {
+                pairs.push((key.to_string(), value.to_string()));
+            }
+        }
+    }
+}
+
+fn quote_mpd_arg(value: &str) -> String {
+    let escaped = value.replace('\\', "\\\\").replace('"', "\\\"");
+    format!("\"{escaped}\"")
+}
+
+fn media_info_from_mpd(song: &[(String, String)], status: &[(String, String)]) -> MediaInfo {
+    let field = |pairs: &[(String, String)], key: &str| {
+        pairs
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.clone())
+    };
+
+    let status = match field(status, "state").as_deref() {
+        Some("play") => MediaStatus::Playing,
+        Some("pause") => MediaStatus::Paused,
+        _ => MediaStatus::Stopped,
+    };
+
+    let title = field(song, "Title")
+        .or_else(|| field(song, "Name"))
+        .or_else(|| {
+            field(song, "file").and_then(|file| {
+                Path::new(&file)
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .map(str::to_string)
+            })
+        })
+        .unwrap_or_default();
+
+    let artists: Vec<&str> = song
+        .iter()
+        .filter(|(key, _)| key == "Artist")
+        .map(|(_, value)| value.as_str())
+        .collect();
+    let artist = if artists.is_empty() {
+        field(song, "AlbumArtist").unwrap_or_default()
+    } else {
+        artists.join(", ")
+    };
+
+    MediaInfo {
+        title,
+        artist,
+        status,
+        art_url: String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    /// Runs a session against `server`, a canned mpd transcript, until the
+    /// transcript runs out. Returns the error that ended it, the commands
+    /// that were sent and every update that was published.
+    fn run_scripted(password: Option<&str>, server: &str) -> (String, String, Vec<MediaInfo>) {
+        let mut source = MpdSource::new("scripted".to_string(), password.map(str::to_string));
+        let (tx, rx) = mpsc::channel();
+        let mut sent = Vec::new();
+        let mut last_sent = MediaInfo::default();
+        let err = source
+            .run_session(
+                BufReader::new(server.as_bytes()),
+                &mut sent,
+                &tx,
+                &mut last_sent,
+            )
+            .unwrap_err();
+        drop(tx);
+        (
+            err.to_string(),
+            String::from_utf8(sent).unwrap(),
+            rx.iter().collect(),
+        )
+    }
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn session_publishes_updates_after_idle() {
+        let server = "OK MPD 0.23.5\n\
+            OK\n\
+            file: music/song.flac\nTitle: Field of Hopes and Dreams\nArtist: Toby Fox\nOK\n\
+            volume: 100\nstate: play\nOK\n\
+            changed: player\nOK\n\
+            file: music/song.flac\nTitle: Field of Hopes and Dreams\nArtist: Toby Fox\nOK\n\
+            state: pause\nOK\n\
+            changed: player\nOK\n\
+            file: music/song.flac\nTitle: Field of Hopes and Dreams\nArtist: Toby Fox\nOK\n\
+            state: pause\nOK\n";
+        let (err, sent, updates) = run_scripted(Some("se\"cret"), server);
+
+        assert_eq!(err, "mpd closed the connection");
+        assert_eq!(
+            sent,
+            "password \"se\\\"cret\"\n\
+             currentsong\nstatus\nidle player\n\
+             currentsong\nstatus\nidle player\n\
+             currentsong\nstatus\nidle player\n"
+        );
+        let playing = MediaInfo {
+            title: "Field of Hopes and Dreams".to_string(),
+            artist: "Toby Fox".to_string(),
+            status: MediaStatus::Playing,
+            art_url: String::new(),
+        };
+        let paused = MediaInfo {
+            status: MediaStatus::Paused,
+            ..playing.clone()
+        };
+        // The third poll is unchanged and is not sent again.
+        assert_eq!(updates, vec![playing, paused]);
+    }
+
+    #[test]
+    fn session_connects_over_tcp() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            stream.write_all(b"OK MPD 0.23.5\n").unwrap();
+            let mut received = Vec::new();
+            // Hang up while idling.
+            for response in ["Title: Hopes and Dreams\nOK\n", "state: play\nOK\n", ""] {
+                let mut command = String::new();
+                reader.read_line(&mut command).unwrap();
+                received.push(command);
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+            received
+        });
+
+        let mut source = MpdSource::new(address, None);
+        let (tx, rx) = mpsc::channel();
+        let mut last_sent = MediaInfo::default();
+        let err = source.session(&tx, &mut last_sent).unwrap_err();
+
+        assert_eq!(err.to_string(), "mpd closed the connection");
+        assert!(source.connected);
+        assert_eq!(
+            server.join().unwrap(),
+            ["currentsong\n", "status\n", "idle player\n"]
+        );
+        assert_eq!(rx.try_recv().unwrap().title, "Hopes and Dreams");
+    }
+
+    #[test]
+    fn session_rejects_unexpected_greeting() {
+        let (err, sent, updates) = run_scripted(None, "HTTP/1.1 400 Bad Request\n");
+        assert_eq!(err, "unexpected mpd greeting: HTTP/1.1 400 Bad Request");
+        assert!(sent.is_empty());
+        assert!(updates.is_empty());
+    }
+
+    #[test]
+    fn session_reports_rejected_password() {
+        let server = "OK MPD 0.23.5\nACK [3@0] {password} incorrect password\n";
+        let (err, sent, updates) = run_scripted(Some("wrong"), server);
+        assert_eq!(
+            err,
+            "mpd rejected \"password \\\"wrong\\\"\": [3@0] {password} incorrect password"
+        );
+        assert_eq!(sent, "password \"wrong\"\n");
+        assert!(updates.is_empty());
+    }
+
+    #[test]
+    fn media_info_falls_back_to_name_and_file() {
+        let stopped = pairs(&[("state", "stop")]);
+
+        let stream = media_info_from_mpd(
+            &pairs(&[("file", "http://radio"), ("Name", "Radio")]),
+            &stopped,
+        );
+        assert_eq!(stream.title, "Radio");
+        assert_eq!(stream.status, MediaStatus::Stopped);
+
+        let file = media_info_from_mpd(&pairs(&[("file", "music/Hopes and Dreams.ogg")]), &[]);
+        assert_eq!(file.title, "Hopes and Dreams");
+        assert_eq!(file.artist, "");
+    }
+
+    #[test]
+    fn media_info_joins_artists() {
+        let song = pairs(&[
+            ("Title", "Megalovania"),
+            ("Artist", "Toby Fox"),
+            ("Artist", "Lena Raine"),
+            ("AlbumArtist", "Various"),
+        ]);
+        let info = media_info_from_mpd(&song, &pairs(&[("state", "play")]));
+        assert_eq!(info.artist, "Toby Fox, Lena Raine");
+        assert_eq!(info.status, MediaStatus::Playing);
+
+        let song = pairs(&[("Title", "Megalovania"), ("AlbumArtist", "Various")]);
+        assert_eq!(media_info_from_mpd(&song, &[]).artist, "Various");
+    }
+}
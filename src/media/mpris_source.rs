@@ -0,0 +1,670 @@
+use super::{MediaInfo, MediaSource, MediaStatus};
+use crate::{PlayerTieBreak, Settings, SettingsState};
+use anyhow::Result;
+use mpris::{PlaybackStatus, PlayerFinder};
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+
+const MPRIS_POLL_INTERVAL: Duration = Duration::from_millis(500);
+const MPRIS_FALLBACK_POLL_INTERVAL: Duration = Duration::from_secs(5);
+const MPRIS_SIGNAL_DEBOUNCE: Duration = Duration::from_millis(30);
+const MPRIS_PATH: &str = "/org/mpris/MediaPlayer2";
+const MPRIS_BUS_PREFIX: &str = "org.mpris.MediaPlayer2.";
+
+pub(crate) struct MprisSource {
+    bus_address: Option<String>,
+    settings_path: PathBuf,
+}
+
+impl MprisSource {
+    pub(crate) fn new(bus_address: Option<String>, settings_path: PathBuf) -> Self {
+        Self {
+            bus_address,
+            settings_path,
+        }
+    }
+}
+
+impl MediaSource for MprisSource {
+    fn run(&mut self, tx: &Sender<MediaInfo>) -> Result<()> {
+        mpris_loop(tx, self.bus_address.as_deref(), &self.settings_path)
+    }
+}
+
+fn mpris_loop(tx: &Sender<MediaInfo>, bus_address: Option<&str>, settings_path: &Path) -> ! {
+    let mut last_sent = MediaInfo::default();
+    let mut selection = PlayerSelection::new(settings_path.to_path_buf());
+    loop {
+        // One connection for querying players is kept for as long as the
+        // signal connection lives, instead of opening one per query.
+        let finder = match mpris_player_finder(bus_address) {
+            Ok(finder) => finder,
+            Err(err) => {
+                eprintln!("Failed to connect to D-Bus: {err}");
+                std::thread::sleep(MPRIS_POLL_INTERVAL);
+                continue;
+            }
+        };
+        let signals = match open_mpris_signal_connection(bus_address) {
+            Ok(connection) => Some(connection),
+            Err(err) => {
+                eprintln!("Failed to subscribe to MPRIS signals, polling instead: {err}");
+                None
+            }
+        };
+
+        selection.reload_rules_if_needed();
+        publish_best_player(tx, &finder, &mut selection, &mut last_sent);
+
+        match signals {
+            Some(connection) => {
+                if let Err(err) = watch_mpris_signals(
+                    &connection,
+                    tx,
+                    &finder,
+                    &mut selection,
+                    &mut last_sent,
+                ) {
+                    eprintln!("Lost MPRIS signal connection: {err}");
+                }
+            }
+            None => {
+                // Poll with the same connection for a while, then try to
+                // subscribe again.
+                let started = Instant::now();
+                while started.elapsed() < MPRIS_FALLBACK_POLL_INTERVAL {
+                    std::thread::sleep(MPRIS_POLL_INTERVAL);
+                    selection.reload_rules_if_needed();
+                    publish_best_player(tx, &finder, &mut selection, &mut last_sent);
+                }
+            }
+        }
+    }
+}
+
+fn open_mpris_signal_connection(bus_address: Option<&str>) -> Result<dbus::blocking::Connection> {
+    let connection = match bus_address {
+        Some(address) => {
+            let mut channel = dbus::channel::Channel::open_private(address)?;
+            channel.register()?;
+            dbus::blocking::Connection::from(channel)
+        }
+        None => dbus::blocking::Connection::new_session()?,
+    };
+
+    connection.add_match_no_cb(&format!(
+        "type='signal',interface='org.freedesktop.DBus.Properties',member='PropertiesChanged',path='{MPRIS_PATH}'"
+    ))?;
+    connection.add_match_no_cb(
+        "type='signal',sender='org.freedesktop.DBus',interface='org.freedesktop.DBus',member='NameOwnerChanged',arg0namespace='org.mpris.MediaPlayer2'",
+    )?;
+
+    Ok(connection)
+}
+
+fn watch_mpris_signals(
+    connection: &dbus::blocking::Connection,
+    tx: &Sender<MediaInfo>,
+    finder: &PlayerFinder,
+    selection: &mut PlayerSelection,
+    last_sent: &mut MediaInfo,
+) -> Result<()> {
+    let channel = connection.channel();
+    loop {
+        // A timeout without any signal doubles as the fallback poll for players
+        // that forget to emit PropertiesChanged.
+        if let Some(message) = channel.blocking_pop_message(MPRIS_FALLBACK_POLL_INTERVAL)? {
+            if !is_mpris_signal(&message) {
+                continue;
+            }
+            // Players tend to emit a burst of signals per track change, so drain
+            // the burst before querying everything once.
+            while channel.blocking_pop_message(MPRIS_SIGNAL_DEBOUNCE)?.is_some() {}
+        }
+
+        selection.reload_rules_if_needed();
+        publish_best_player(tx, finder, selection, last_sent);
+    }
+}
+
+fn is_mpris_signal(message: &dbus::Message) -> bool {
+    if message.msg_type() != dbus::MessageType::Signal {
+        return false;
+    }
+
+    match message.member().as_deref() {
+        Some("PropertiesChanged") => message.path().as_deref() == Some(MPRIS_PATH),
+        Some("NameOwnerChanged") => message
+            .get1::<&str>()
+            .is_some_and(|name| name.starts_with(MPRIS_BUS_PREFIX)),
+        _ => false,
+    }
+}
+
+fn publish_best_player(
+    tx: &Sender<MediaInfo>,
+    finder: &PlayerFinder,
+    selection: &mut PlayerSelection,
+    last_sent: &mut MediaInfo,
+) {
+    let players = finder.find_all().unwrap_or_default();
+    let mut candidates = Vec::with_capacity(players.len());
+    for player in players {
+        let status = match player.get_playback_status() {
+            Ok(status) => status,
+            Err(_) => continue,
+        };
+        let metadata = player.get_metadata().ok();
+        let title = metadata
+            .as_ref()
+            .and_then(|m| m.title())
+            .unwrap_or("")
+            .to_string();
+        let artist = metadata
+            .as_ref()
+            .and_then(|m| m.artists())
+            .map(|artists| artists.join(", "))
+            .unwrap_or_default();
+        let art_url = metadata
+            .as_ref()
+            .and_then(|m| m.art_url())
+            .unwrap_or("")
+            .to_string();
+
+        candidates.push(PlayerCandidate {
+            bus_name: player.bus_name().to_string(),
+            identity: player.identity().to_string(),
+            info: MediaInfo {
+                title,
+                artist,
+                status: map_status(status),
+                art_url,
+            },
+        });
+    }
+
+    let next = selection.select(candidates).unwrap_or_default();
+    if next != *last_sent {
+        let _ = tx.send(next.clone());
+        *last_sent = next;
+    }
+}
+
+fn mpris_player_finder(bus_address: Option<&str>) -> Result<PlayerFinder> {
+    match bus_address {
+        Some(address) => {
+            let connection = dbus::ffidisp::Connection::open_private(address)?;
+            connection.register()?;
+            Ok(PlayerFinder::for_connection(connection))
+        }
+        None => Ok(PlayerFinder::new()?),
+    }
+}
+
+fn map_status(status: PlaybackStatus) -> MediaStatus {
+    match status {
+        PlaybackStatus::Playing => MediaStatus::Playing,
+        PlaybackStatus::Paused => MediaStatus::Paused,
+        PlaybackStatus::Stopped => MediaStatus::Stopped,
+    }
+}
+
+fn status_rank(status: &MediaStatus) -> i32 {
+    match status {
+        MediaStatus::Playing => 3,
+        MediaStatus::Paused => 2,
+        MediaStatus::Stopped => 1,
+    }
+}
+
+struct PlayerCandidate {
+    bus_name: String,
+    identity: String,
+    info: MediaInfo,
+}
+
+struct PlayerRules {
+    allowed: Vec<Regex>,
+    blocked: Vec<Regex>,
+    priority: Vec<Regex>,
+    tie_break: PlayerTieBreak,
+}
+
+impl PlayerRules {
+    fn from_settings(settings: &Settings) -> Self {
+        Self {
+            allowed: compile_player_patterns(&settings.allowed_players),
+            blocked: compile_player_patterns(&settings.blocked_players),
+            priority: compile_player_patterns(&settings.player_priority),
+            tie_break: settings.player_tie_break,
+        }
+    }
+
+    fn accepts(&self, candidate: &PlayerCandidate) -> bool {
+        if !self.allowed.is_empty()
+            && !self.allowed.iter().any(|pattern| player_matches(pattern, candidate))
+        {
+            return false;
+        }
+        !self.blocked.iter().any(|pattern| player_matches(pattern, candidate))
+    }
+
+    fn priority_rank(&self, candidate: &PlayerCandidate) -> usize {
+        self.priority
+            .iter()
+            .position(|pattern| player_matches(pattern, candidate))
+            .unwrap_or(self.priority.len())
+    }
+}
+
+struct PlayerSelection {
+    settings_path: PathBuf,
+    settings_state: SettingsState,
+    rules: PlayerRules,
+    started_playing: HashMap<String, Instant>,
+}
+
+impl PlayerSelection {
+    fn new(settings_path: PathBuf) -> Self {
+        let settings = Settings::load(&settings_path).unwrap_or_default();
+        Self {
+            settings_state: SettingsState::new(&settings_path),
+            settings_path,
+            rules: PlayerRules::from_settings(&settings),
+            started_playing: HashMap::new(),
+        }
+    }
+
+    fn reload_rules_if_needed(&mut self) {
+        let modified = fs::metadata(&self.settings_path)
+            .and_then(|meta| meta.modified())
+            .ok();
+        if modified.is_none() || modified == self.settings_state.last_modified {
+            return;
+        }
+
+        if let Ok(settings) = Settings::load(&self.settings_path) {
+            self.rules = PlayerRules::from_settings(&settings);
+        }
+        self.settings_state.last_modified = modified;
+    }
+
+    fn select(&mut self, candidates: Vec<PlayerCandidate>) -> Option<MediaInfo> {
+        let now = Instant::now();
+        self.started_playing
+            .retain(|bus_name, _| candidates.iter().any(|c| &c.bus_name == bus_name));
+        for candidate in &candidates {
+            if candidate.info.status == MediaStatus::Playing {
+                self.started_playing
+                    .entry(candidate.bus_name.clone())
+                    .or_insert(now);
+            } else {
+                self.started_playing.remove(&candidate.bus_name);
+            }
+        }
+
+        let mut best: Option<PlayerCandidate> = None;
+        for candidate in candidates {
+            if !self.rules.accepts(&candidate) {
+                continue;
+            }
+            if best.as_ref().is_none_or(|current| self.is_better(&candidate, current)) {
+                best = Some(candidate);
+            }
+        }
+        best.map(|candidate| candidate.info)
+    }
+
+    fn is_better(&self, candidate: &PlayerCandidate, current: &PlayerCandidate) -> bool {
+        let candidate_rank = status_rank(&candidate.info.status);
+        let current_rank = status_rank(&current.info.status);
+        if candidate_rank != current_rank {
+            return candidate_rank > current_rank;
+        }
+
+        let candidate_priority = self.rules.priority_rank(candidate);
+        let current_priority = self.rules.priority_rank(current);
+        if candidate_priority != current_priority {
+            return candidate_priority < current_priority;
+        }
+
+        match self.rules.tie_break {
+            PlayerTieBreak::BusOrder => false,
+            PlayerTieBreak::MostRecentlyPlaying => {
+                match (
+                    self.started_playing.get(&candidate.bus_name),
+                    self.started_playing.get(&current.bus_name),
+                ) {
+                    (Some(candidate_started), Some(current_started)) => {
+                        candidate_started > current_started
+                    }
+                    // Only playing players are timed, and both have the same
+                    // status here, so untimed players keep bus order.
+                    _ => false,
+                }
+            }
+        }
+    }
+}
+
+/// Compiles player patterns from the settings. Plain patterns are case-insensitive
+/// globs (`*` and `?`), patterns prefixed with `re:` are regular expressions.
+fn compile_player_patterns(patterns: &[String]) -> Vec<Regex> {
+    patterns
+        .iter()
+        .filter_map(|pattern| match compile_player_pattern(pattern) {
+            Ok(regex) => Some(regex),
+            Err(err) => {
+                eprintln!("Ignoring invalid player pattern {pattern:?}: {err}");
+                None
+            }
+        })
+        .collect()
+}
+
+fn compile_player_pattern(pattern: &str) -> Result<Regex> {
+    if let Some(expr) = pattern.strip_prefix("re:") {
+        return Ok(Regex::new(expr)?);
+    }
+
+    let mut expr = String::from("(?i)^");
+    let mut literal = String::new();
+    for ch in pattern.chars() {
+        match ch {
+            '*' | '?' => {
+                expr.push_str(&regex::escape(&literal));
+                literal.clear();
+                expr.push_str(if ch == '*' { ".*" } else { "." });
+            }
+            _ => literal.push(ch),
+        }
+    }
+    expr.push_str(&regex::escape(&literal));
+    expr.push('$');
+    Ok(Regex::new(&expr)?)
+}
+
+fn player_matches(pattern: &Regex, candidate: &PlayerCandidate) -> bool {
+    let short_name = candidate
+        .bus_name
+        .strip_prefix(MPRIS_BUS_PREFIX)
+        .unwrap_or(&candidate.bus_name);
+    pattern.is_match(&candidate.bus_name)
+        || pattern.is_match(short_name)
+        || pattern.is_match(&candidate.identity)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(bus_name: &str, identity: &str, status: MediaStatus) -> PlayerCandidate {
+        PlayerCandidate {
+            bus_name: format!("{MPRIS_BUS_PREFIX}{bus_name}"),
+            identity: identity.to_string(),
+            info: MediaInfo {
+                title: bus_name.to_string(),
+                status,
+                ..MediaInfo::default()
+            },
+        }
+    }
+
+    fn selection(settings: Settings) -> PlayerSelection {
+        let settings_path = PathBuf::from("/nonexistent/Settings.json");
+        PlayerSelection {
+            settings_state: SettingsState::new(&settings_path),
+            settings_path,
+            rules: PlayerRules::from_settings(&settings),
+            started_playing: HashMap::new(),
+        }
+    }
+
+    fn patterns(patterns: &[&str]) -> Vec<String> {
+        patterns.iter().map(|pattern| pattern.to_string()).collect()
+    }
+
+    fn selected_title(selection: &mut PlayerSelection, candidates: Vec<PlayerCandidate>) -> String {
+        selection
+            .select(candidates)
+            .map(|info| info.title)
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn glob_patterns_are_anchored_and_case_insensitive() {
+        let pattern = compile_player_pattern("Fire*.instance?").unwrap();
+        assert!(pattern.is_match("firefox.instance1"));
+        assert!(!pattern.is_match("firefox.instance12"));
+        assert!(!pattern.is_match("my-firefox.instance1"));
+        // Regex syntax in a glob is literal.
+        let pattern = compile_player_pattern("a.b+").unwrap();
+        assert!(pattern.is_match("A.B+"));
+        assert!(!pattern.is_match("axbb"));
+    }
+
+    #[test]
+    fn regex_patterns_are_used_as_written() {
+        let pattern = compile_player_pattern("re:^chrom(e|ium)").unwrap();
+        assert!(pattern.is_match("chromium.instance2"));
+        assert!(!pattern.is_match("Chromium"));
+        assert!(compile_player_pattern("re:(").is_err());
+        assert_eq!(
+            compile_player_patterns(&patterns(&["re:(", "mpd"])).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn patterns_match_bus_name_short_name_or_identity() {
+        let player = candidate("spotify", "Spotify Premium", MediaStatus::Playing);
+        for pattern in [
+            "org.mpris.MediaPlayer2.spotify",
+            "spotify",
+            "spotify premium",
+        ] {
+            assert!(
+                player_matches(&compile_player_pattern(pattern).unwrap(), &player),
+                "{pattern}"
+            );
+        }
+        assert!(!player_matches(
+            &compile_player_pattern("premium").unwrap(),
+            &player
+        ));
+    }
+
+    #[test]
+    fn blocked_players_win_over_allowed_ones() {
+        let mut selection = selection(Settings {
+            allowed_players: patterns(&["firefox*", "mpd"]),
+            blocked_players: patterns(&["*.instance2"]),
+            ..Settings::default()
+        });
+        let title = selected_title(
+            &mut selection,
+            vec![
+                candidate("spotify", "Spotify", MediaStatus::Playing),
+                candidate("firefox.instance2", "Mozilla Firefox", MediaStatus::Playing),
+                candidate("mpd", "Music Player Daemon", MediaStatus::Paused),
+            ],
+        );
+        assert_eq!(title, "mpd");
+        let title = selected_title(
+            &mut selection,
+            vec![candidate("spotify", "Spotify", MediaStatus::Playing)],
+        );
+        assert_eq!(title, "");
+    }
+
+    #[test]
+    fn status_comes_before_priority() {
+        let mut selection = selection(Settings {
+            player_priority: patterns(&["mpd", "spotify"]),
+            ..Settings::default()
+        });
+        let title = selected_title(
+            &mut selection,
+            vec![
+                candidate("mpd", "Music Player Daemon", MediaStatus::Paused),
+                candidate("vlc", "VLC media player", MediaStatus::Playing),
+            ],
+        );
+        assert_eq!(title, "vlc");
+        let title = selected_title(
+            &mut selection,
+            vec![
+                candidate("vlc", "VLC media player", MediaStatus::Playing),
+                candidate("spotify", "Spotify", MediaStatus::Playing),
+                candidate("mpd", "Music Player Daemon", MediaStatus::Playing),
+            ],
+        );
+        assert_eq!(title, "mpd");
+    }
+
+    #[test]
+    fn tie_break_prefers_the_player_that_started_last() {
+        let mut selection = selection(Settings::default());
+        let vlc_playing = || candidate("vlc", "VLC media player", MediaStatus::Playing);
+        let mpd = |status| candidate("mpd", "Music Player Daemon", status);
+        assert_eq!(
+            selected_title(
+                &mut selection,
+                vec![vlc_playing(), mpd(MediaStatus::Paused)]
+            ),
+            "vlc"
+        );
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(
+            selected_title(
+                &mut selection,
+                vec![vlc_playing(), mpd(MediaStatus::Playing)]
+            ),
+            "mpd"
+        );
+
+        selection.rules.tie_break = PlayerTieBreak::BusOrder;
+        assert_eq!(
+            selected_title(
+                &mut selection,
+                vec![vlc_playing(), mpd(MediaStatus::Playing)]
+            ),
+            "vlc"
+        );
+    }
+
+    #[test]
+    fn untimed_players_keep_bus_order() {
+        let mut selection = selection(Settings::default());
+        let title = selected_title(
+            &mut selection,
+            vec![
+                candidate("vlc", "VLC media player", MediaStatus::Paused),
+                candidate("mpd", "Music Player Daemon", MediaStatus::Paused),
+            ],
+        );
+        assert_eq!(title, "vlc");
+    }
+
+    /// Kills the helper processes of a test, even when it fails.
+    struct ChildGuard(Vec<std::process::Child>);
+
+    impl Drop for ChildGuard {
+        fn drop(&mut self) {
+            for child in &mut self.0 {
+                let _ = child.kill();
+                let _ = child.wait();
+            }
+        }
+    }
+
+    /// Starts the fake_mpris_player example, which `cargo test` builds next to
+    /// the test binaries, on the bus at `address`.
+    fn spawn_fake_player(address: &str) -> std::process::Child {
+        let examples = std::env::current_exe()
+            .unwrap()
+            .parent()
+            .and_then(Path::parent)
+            .unwrap()
+            .join("examples");
+        std::process::Command::new(examples.join("fake_mpris_player"))
+            .args(["--bus-address", address, "--name", "fake"])
+            .stdin(std::process::Stdio::piped())
+            .spawn()
+            .expect("fake_mpris_player is built by cargo test")
+    }
+
+    fn send_command(player: &mut std::process::Child, command: &str) {
+        use std::io::Write;
+        let stdin = player.stdin.as_mut().unwrap();
+        writeln!(stdin, "{command}").unwrap();
+        stdin.flush().unwrap();
+    }
+
+    /// Waits for the next update, well short of the fallback poll, so only a
+    /// signal can deliver it in time.
+    fn next_update(rx: &std::sync::mpsc::Receiver<MediaInfo>) -> MediaInfo {
+        rx.recv_timeout(MPRIS_FALLBACK_POLL_INTERVAL / 2)
+            .expect("no update before the fallback poll")
+    }
+
+    #[test]
+    #[ignore = "needs dbus-daemon"]
+    fn signals_publish_updates_without_polling() {
+        use std::io::BufRead;
+
+        let mut daemon = std::process::Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address"])
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .expect("dbus-daemon is installed");
+        let mut address = String::new();
+        std::io::BufReader::new(daemon.stdout.take().unwrap())
+            .read_line(&mut address)
+            .unwrap();
+        let address = address.trim().to_string();
+        let mut children = ChildGuard(vec![daemon]);
+
+        children.0.push(spawn_fake_player(&address));
+        let (tx, rx) = std::sync::mpsc::channel();
+        let source_address = address.clone();
+        std::thread::spawn(move || {
+            let settings_path = PathBuf::from("/nonexistent/Settings.json");
+            MprisSource::new(Some(source_address), settings_path).run(&tx)
+        });
+
+        // The first update may still come from the query at startup. Once it
+        // is in, the source is subscribed and everything after is signalled.
+        let player = children.0.last_mut().unwrap();
+        send_command(player, "play Field of Hopes and Dreams | Toby Fox");
+        let info = rx
+            .recv_timeout(MPRIS_FALLBACK_POLL_INTERVAL * 2)
+            .expect("no update after the first play");
+        assert_eq!(info.title, "Field of Hopes and Dreams");
+
+        send_command(player, "play Rude Buster | Toby Fox");
+        let info = next_update(&rx);
+        assert_eq!(info.title, "Rude Buster");
+        assert_eq!(info.status, MediaStatus::Playing);
+
+        send_command(player, "pause");
+        assert_eq!(next_update(&rx).status, MediaStatus::Paused);
+
+        // The player leaving and a new one showing up come as NameOwnerChanged.
+        send_command(player, "quit");
+        assert_eq!(next_update(&rx), MediaInfo::default());
+        children.0.push(spawn_fake_player(&address));
+        let player = children.0.last_mut().unwrap();
+        send_command(player, "play Big Shot | Toby Fox");
+        let mut info = next_update(&rx);
+        // The new player can be seen before it starts playing.
+        if info.title.is_empty() {
+            info = next_update(&rx);
+        }
+        assert_eq!(info.title, "Big Shot");
+    }
+}
@@ -0,0 +1,31 @@
+use super::{parse_media_update, MediaInfo, MediaSource};
+use anyhow::Result;
+use std::io::BufRead;
+use std::sync::mpsc::Sender;
+
+/// Reads one JSON object per line from stdin, so any script can feed the
+/// overlay with `{"title": "...", "artist": "...", "status": "playing"}`.
+pub(crate) struct StdinSource;
+
+impl MediaSource for StdinSource {
+    fn run(&mut self, tx: &Sender<MediaInfo>) -> Result<()> {
+        let mut last_sent = MediaInfo::default();
+        for (index, line) in std::io::stdin().lock().lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            match parse_media_update(&line) {
+                Ok(next) => {
+                    if next != last_sent {
+                        let _ = tx.send(next.clone());
+                        last_sent = next;
+                    }
+                }
+                Err(err) => eprintln!("Ignoring stdin line {}: {err}", index + 1),
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,1310 @@
+use crate::album_art::{draw_album_art, load_album_art};
+use crate::media::{MediaInfo, MediaStatus};
+use crate::panel::{draw_panel, load_panel_frame, PanelRect};
+use crate::ttf::TtfFallback;
+use crate::{
+    draw_text, fill_background, load_assets, measure_line, AlbumArt, AnimationMode,
+    AnimationSettings, ArtPosition, BitmapFont, Easing, FontAtlas, OverflowMode, Panel, Settings,
+    SettingsState, SlideDirection, TextAlign, TextMargin, TextStyle,
+};
+use anyhow::Result;
+use image::RgbaImage;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+struct MediaState {
+    info: MediaInfo,
+    last_update: Instant,
+}
+
+impl Default for MediaState {
+    fn default() -> Self {
+        Self {
+            info: MediaInfo::default(),
+            last_update: Instant::now(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DisplayState {
+    Hidden,
+    AppearingDelay,
+    Appearing,
+    Visible,
+    Disappearing,
+}
+
+struct DisplaySlot {
+    text: String,
+    /// How many lines of `text` are the title, the rest are the artist.
+    title_lines: usize,
+    state: DisplayState,
+    timer: f32,
+    opacity: f32,
+    offset_x: f32,
+    offset_y: f32,
+    /// How many characters of `text` are drawn, for the typewriter reveal.
+    visible_glyphs: usize,
+    /// Time since `text` last changed, which drives the marquee.
+    marquee_timer: f32,
+    /// Album art thumbnail of the media `text` is about.
+    art: Option<Arc<RgbaImage>>,
+}
+
+struct DisplayController {
+    slots: [DisplaySlot; 2],
+    primary_index: usize,
+    current_media: MediaInfo,
+    current_art: Option<Arc<RgbaImage>>,
+}
+
+impl DisplayController {
+    fn new() -> Self {
+        Self {
+            slots: [
+                DisplaySlot {
+                    text: String::new(),
+                    title_lines: 0,
+                    state: DisplayState::Hidden,
+                    timer: 0.0,
+                    opacity: 0.0,
+                    offset_x: 0.0,
+                    offset_y: 0.0,
+                    visible_glyphs: usize::MAX,
+                    marquee_timer: 0.0,
+                    art: None,
+                },
+                DisplaySlot {
+                    text: String::new(),
+                    title_lines: 0,
+                    state: DisplayState::Hidden,
+                    timer: 0.0,
+                    opacity: 0.0,
+                    offset_x: 0.0,
+                    offset_y: 0.0,
+                    visible_glyphs: usize::MAX,
+                    marquee_timer: 0.0,
+                    art: None,
+                },
+            ],
+            primary_index: 0,
+            current_media: MediaInfo::default(),
+            current_art: None,
+        }
+    }
+
+    /// Both slots have finished hiding, so there is nothing left to draw.
+    fn is_idle(&self) -> bool {
+        self.slots
+            .iter()
+            .all(|slot| slot.state == DisplayState::Hidden)
+    }
+
+    /// A slot is fading or sliding and needs a new frame on every refresh.
+    fn is_animating(&self) -> bool {
+        self.slots.iter().any(|slot| {
+            matches!(
+                slot.state,
+                DisplayState::AppearingDelay | DisplayState::Appearing | DisplayState::Disappearing
+            )
+        })
+    }
+
+    /// Time until a visible slot starts hiding on its own, measured from the
+    /// last `update_display_slot` call. `None` means only a media update or a
+    /// settings change can change what is on screen.
+    fn next_timeout(&self, settings: &Settings, media: &MediaState) -> Option<Duration> {
+        self.slots
+            .iter()
+            .filter(|slot| slot.state == DisplayState::Visible)
+            .filter_map(|slot| {
+                let hide_after = match settings.hide_automatically {
+                    Some(hide_after) => hide_after,
+                    None if !settings.show_playback_status
+                        && media.info.status != MediaStatus::Playing =>
+                    {
+                        settings.animation.stay_time
+                    }
+                    None => return None,
+                };
+                Some(Duration::from_secs_f32((hide_after - slot.timer).max(0.0)))
+            })
+            .min()
+    }
+}
+
+/// Everything about the overlay that does not depend on how it ends up on
+/// screen: settings, the current media, the two display slots and rendering
+/// them into a canvas. Backends only present the pixels.
+pub(crate) struct OverlayCore {
+    settings_path: PathBuf,
+    pub(crate) settings: Settings,
+    settings_state: SettingsState,
+    pub(crate) font: BitmapFont,
+    atlas: FontAtlas,
+    ttf: TtfFallback,
+    panel_frame: Option<RgbaImage>,
+    /// Decodes album art off the render thread. Without one, as when
+    /// rendering headless, art is decoded as soon as it is needed.
+    art_loader: Option<ArtLoader>,
+    /// Counts art requests, so art for media that already changed again is
+    /// thrown away.
+    art_generation: u64,
+    media: MediaState,
+    display: DisplayController,
+    last_frame: Instant,
+}
+
+impl OverlayCore {
+    pub(crate) fn new(
+        settings_path: PathBuf,
+        settings: Settings,
+        settings_state: SettingsState,
+        font: BitmapFont,
+        atlas: FontAtlas,
+    ) -> Self {
+        Self {
+            ttf: TtfFallback::new(&settings),
+            panel_frame: load_panel_frame(settings.panel.as_ref()),
+            art_loader: None,
+            art_generation: 0,
+            settings_path,
+            settings,
+            settings_state,
+            font,
+            atlas,
+            media: MediaState::default(),
+            display: DisplayController::new(),
+            last_frame: Instant::now(),
+        }
+    }
+
+    pub(crate) fn set_media(&mut self, info: MediaInfo) {
+        self.media.info = info;
+        self.media.last_update = Instant::now();
+    }
+
+    pub(crate) fn reload_settings_if_needed(&mut self) -> bool {
+        if self.settings_state.last_check.elapsed() < SETTINGS_CHECK_INTERVAL {
+            return false;
+        }
+        self.settings_state.last_check = Instant::now();
+
+        let metadata = match fs::metadata(&self.settings_path) {
+            Ok(metadata) => metadata,
+            Err(_) => return false,
+        };
+        let modified = metadata.modified().ok();
+        if modified.is_none() || modified == self.settings_state.last_modified {
+            return false;
+        }
+
+        if let Ok(settings) = Settings::load(&self.settings_path) {
+            // A new font stack also drops the glyphs rasterized into the old
+            // atlas. Otherwise those stay, and new TrueType fonts or options
+            // only apply to characters that show up later.
+            if settings.fonts != self.settings.fonts {
+                (self.font, self.atlas) = load_assets(&settings.fonts);
+            }
+            if settings.fonts != self.settings.fonts
+                || settings.ttf_fallback_fonts != self.settings.ttf_fallback_fonts
+                || settings.ttf_threshold != self.settings.ttf_threshold
+                || settings.ttf_pixel_snap != self.settings.ttf_pixel_snap
+            {
+                self.ttf = TtfFallback::new(&settings);
+            }
+            let frame = |settings: &Settings| {
+                settings
+                    .panel
+                    .as_ref()
+                    .and_then(|panel| panel.frame.clone())
+            };
+            if frame(&settings) != frame(&self.settings) {
+                self.panel_frame = load_panel_frame(settings.panel.as_ref());
+            }
+            let reload_art = settings.album_art != self.settings.album_art;
+            self.settings = settings;
+            if reload_art {
+                self.load_current_art();
+                self.show_current_art();
+            }
+        }
+        self.settings_state.last_modified = modified;
+        true
+    }
+
+    /// Advances the display slots to `now` and returns whether the frame on
+    /// screen is out of date.
+    pub(crate) fn tick(&mut self, now: Instant) -> bool {
+        // Coming back from idle, the time spent asleep must not count towards
+        // the next animation.
+        let dt = if self.display.is_idle() {
+            0.0
+        } else {
+            now.duration_since(self.last_frame).as_secs_f32()
+        };
+        self.last_frame = now;
+
+        let art_arrived = self.receive_art();
+        let previous_states = self.display.slots.each_ref().map(|slot| slot.state);
+        self.update_display_state(dt);
+        let states = self.display.slots.each_ref().map(|slot| slot.state);
+        states != previous_states || self.is_animating() || art_arrived
+    }
+
+    pub(crate) fn is_animating(&self) -> bool {
+        self.display.is_animating() || self.is_scrolling()
+    }
+
+    /// A line on screen is wider than `max_width` and runs as a marquee.
+    fn is_scrolling(&self) -> bool {
+        if self.settings.marquee_speed <= 0.0 {
+            return false;
+        }
+        let scale = device_text_scale(&self.settings, 1.0);
+        self.display.slots.iter().any(|slot| {
+            let Some(max_text_width) = max_text_width(&self.settings, slot.art.is_some(), 1.0)
+            else {
+                return false;
+            };
+            slot.state != DisplayState::Hidden
+                && styled_lines(&self.settings, slot).any(|(line, style)| {
+                    measure_line(line, &self.font, scale * style.scale) > max_text_width
+                })
+        })
+    }
+
+    pub(crate) fn is_idle(&self) -> bool {
+        self.display.is_idle()
+    }
+
+    /// Hides everything and forgets the current media, as if freshly started.
+    pub(crate) fn reset(&mut self) {
+        self.media = MediaState::default();
+        self.display = DisplayController::new();
+        self.art_generation += 1;
+    }
+
+    /// Time from the last `tick` until the display changes on its own.
+    pub(crate) fn next_timeout(&self) -> Option<Duration> {
+        self.display.next_timeout(&self.settings, &self.media)
+    }
+
+    /// Size of the canvas the visible text needs, in device pixels.
+    pub(crate) fn canvas_size(&self, buffer_scale: f64) -> (u32, u32) {
+        let scale = device_text_scale(&self.settings, buffer_scale);
+        let padding = PADDING * buffer_scale as f32;
+
+        // Each slot needs room for its text, its album art if it has any and
+        // the margins around both.
+        let text_margin = text_margin(&self.settings, scale);
+        let mut max_width: f32 = 1.0;
+        let mut max_height: f32 =
+            self.font.line_height * scale + text_margin.top + text_margin.bottom;
+        for slot in self.display.slots.iter() {
+            if slot.state == DisplayState::Hidden || slot.opacity <= 0.0 || slot.text.is_empty() {
+                continue;
+            }
+            let max_line_width = max_text_width(&self.settings, slot.art.is_some(), buffer_scale);
+            let (w, h) = self.text_size(slot, scale, max_line_width.unwrap_or(f32::INFINITY));
+            let margin = content_margin(&self.settings, scale, slot.art.is_some());
+            max_width = max_width.max(w + margin.left + margin.right);
+            max_height = max_height.max(self.content_height(slot, h, scale));
+        }
+
+        let inset = panel_inset(&self.settings, scale);
+        let (room_above, room_below) = self.vertical_slide_room(buffer_scale);
+        let max_height = max_height + inset * 2.0 + room_above + room_below;
+        let width = ((max_width + padding * 2.0) * self.settings.scale_x)
+            .max(1.0)
+            .ceil() as u32;
+        let height = ((max_height + padding * 2.0) * self.settings.scale_y)
+            .max(1.0)
+            .ceil() as u32;
+        (width, height)
+    }
+
+    /// How far text sliding up or down currently reaches above and below its
+    /// resting place, in device pixels. The canvas grows by this much so the
+    /// text is not clipped.
+    pub(crate) fn vertical_slide_room(&self, buffer_scale: f64) -> (f32, f32) {
+        let mut above: f32 = 0.0;
+        let mut below: f32 = 0.0;
+        for slot in self.display.slots.iter() {
+            if slot.state == DisplayState::Hidden || slot.opacity <= 0.0 || slot.text.is_empty() {
+                continue;
+            }
+            let offset = slot.offset_y * buffer_scale as f32;
+            above = above.max(-offset);
+            below = below.max(offset);
+        }
+        (above.ceil(), below.ceil())
+    }
+
+    /// Draws both slots into a BGRA canvas of `width` x `height` device pixels.
+    pub(crate) fn render(&self, canvas: &mut [u8], width: u32, height: u32, buffer_scale: f64) {
+        let scale = device_text_scale(&self.settings, buffer_scale);
+        let padding = PADDING * buffer_scale as f32;
+
+        // An idle overlay stays mapped but fully transparent, even with a background.
+        if self.display.is_idle() {
+            canvas.fill(0);
+        } else {
+            fill_background(
+                canvas,
+                self.settings.force_opaque_background,
+                self.settings.background_opacity,
+            );
+        }
+
+        // The canvas can be wider than needed (the X11 window spans the
+        // monitor), so clip to whichever is narrower.
+        let text_margin = text_margin(&self.settings, scale);
+        let align = self.settings.text_anchor.text_align();
+        let (room_above, _) = self.vertical_slide_room(buffer_scale);
+
+        for slot in self.display.slots.iter() {
+            if slot.state == DisplayState::Hidden || slot.opacity <= 0.0 || slot.text.is_empty() {
+                continue;
+            }
+            let margin = content_margin(&self.settings, scale, slot.art.is_some());
+            let clip_width = max_text_width(&self.settings, slot.art.is_some(), buffer_scale)
+                .unwrap_or(f32::INFINITY)
+                .min(width as f32 - padding * 2.0 - margin.left - margin.right);
+            let origin_x = match align {
+                TextAlign::Left => padding + margin.left,
+                TextAlign::Right => width as f32 - padding - margin.right,
+            } + slot.offset_x * buffer_scale as f32;
+            let origin_y = padding + room_above + margin.top + slot.offset_y * buffer_scale as f32;
+
+            // The panel goes around the text and the album art, which sits
+            // at the top of the text on either side.
+            let (text_width, text_height) = self.text_size(slot, scale, clip_width);
+            let text_left = match align {
+                TextAlign::Left => origin_x,
+                TextAlign::Right => origin_x - text_width,
+            };
+            let content_left = text_left - margin.left + panel_inset(&self.settings, scale);
+            let content_right =
+                text_left + text_width + margin.right - panel_inset(&self.settings, scale);
+            let content_top = origin_y - text_margin.top;
+
+            if let Some(panel) = &self.settings.panel {
+                let inset = panel.inset() * scale;
+                let rect = PanelRect {
+                    x: content_left - inset,
+                    y: content_top - inset,
+                    width: content_right - content_left + inset * 2.0,
+                    height: self.content_height(slot, text_height, scale) + inset * 2.0,
+                };
+                draw_panel(
+                    canvas,
+                    width,
+                    panel,
+                    self.panel_frame.as_ref(),
+                    rect,
+                    scale,
+                    slot.opacity,
+                );
+            }
+            if let (Some(art), Some(thumbnail)) = (&self.settings.album_art, &slot.art) {
+                let size = art.size as f32 * scale;
+                let art_x = match art.position {
+                    ArtPosition::Left => content_left,
+                    ArtPosition::Right => content_right - size,
+                };
+                draw_album_art(
+                    canvas,
+                    width,
+                    thumbnail,
+                    art_x,
+                    content_top,
+                    size,
+                    slot.opacity,
+                );
+            }
+
+            // Lines are drawn one by one since each that is too wide scrolls
+            // on its own.
+            let mut visible_glyphs = slot.visible_glyphs;
+            let mut line_y = origin_y;
+            for (line, style) in styled_lines(&self.settings, slot) {
+                let line_scale = scale * style.scale;
+                let line_width = measure_line(line, &self.font, line_scale);
+                let (line_x, line_align, clip) = if line_width > clip_width {
+                    let clip_left = match align {
+                        TextAlign::Left => origin_x,
+                        TextAlign::Right => origin_x - clip_width,
+                    };
+                    let overflow = (line_width - clip_width) / buffer_scale as f32;
+                    let scroll = marquee_offset(&self.settings, overflow, slot.marquee_timer)
+                        * buffer_scale as f32;
+                    // The outline may stick out of the clip as far as it
+                    // sticks out of the canvas.
+                    let clip = (
+                        clip_left - text_margin.left,
+                        clip_left + clip_width + text_margin.right,
+                    );
+                    (clip_left - scroll, TextAlign::Left, Some(clip))
+                } else {
+                    (origin_x, align, None)
+                };
+                draw_text(
+                    canvas,
+                    width,
+                    height,
+                    &self.font,
+                    &self.atlas,
+                    line,
+                    visible_glyphs,
+                    line_scale,
+                    line_x,
+                    line_y,
+                    slot.opacity,
+                    style,
+                    line_align,
+                    clip,
+                );
+                visible_glyphs = visible_glyphs.saturating_sub(line.chars().count());
+                line_y += self.font.line_height * line_scale;
+            }
+        }
+    }
+
+    /// Height of the text in `slot` with its outline, or of the album art if
+    /// that is taller, in device pixels.
+    fn content_height(&self, slot: &DisplaySlot, text_height: f32, scale: f32) -> f32 {
+        let text_margin = text_margin(&self.settings, scale);
+        let text_height = text_height + text_margin.top + text_margin.bottom;
+        match (&self.settings.album_art, &slot.art) {
+            (Some(art), Some(_)) => text_height.max(art.size as f32 * scale),
+            _ => text_height,
+        }
+    }
+
+    /// Starts loading the album art of the current media, if it is shown at
+    /// all. The current media has no art until it arrives.
+    fn load_current_art(&mut self) {
+        self.art_generation += 1;
+        self.display.current_art = None;
+        let url = &self.display.current_media.art_url;
+        let Some(settings) = self.settings.album_art.filter(|_| !url.is_empty()) else {
+            return;
+        };
+        match &self.art_loader {
+            Some(loader) => {
+                let _ = loader.requests.send(ArtRequest {
+                    generation: self.art_generation,
+                    url: url.clone(),
+                    settings,
+                });
+            }
+            None => self.display.current_art = load_album_art(url, &settings).map(Arc::new),
+        }
+    }
+
+    /// Shows the art the loader finished since the last frame, if it is still
+    /// for the current media. Returns whether there was any.
+    fn receive_art(&mut self) -> bool {
+        let Some(loader) = &self.art_loader else {
+            return false;
+        };
+        let art = loader
+            .results
+            .try_iter()
+            .filter(|loaded| loaded.generation == self.art_generation)
+            .last()
+            .and_then(|loaded| loaded.art);
+        let Some(art) = art else {
+            return false;
+        };
+        self.display.current_art = Some(Arc::new(art));
+        self.show_current_art();
+        true
+    }
+
+    /// Puts the current art next to the current text without replaying its
+    /// animation, since players often send the art a moment after the title.
+    /// The lines were fitted to the room the old art left, so they are
+    /// fitted again.
+    fn show_current_art(&mut self) {
+        let slot = &mut self.display.slots[self.display.primary_index];
+        slot.art = self.display.current_art.clone();
+        if matches!(
+            slot.state,
+            DisplayState::Hidden | DisplayState::Disappearing
+        ) {
+            return;
+        }
+        let (text, title_lines) = format_media_text(
+            &self.settings,
+            &self.font,
+            &self.display.current_media,
+            slot.art.is_some(),
+        );
+        update_slot_text(slot, text, title_lines);
+    }
+
+    /// Size of the text in `slot` in device pixels, counting lines wider than
+    /// `max_width` as that wide since they scroll.
+    fn text_size(&self, slot: &DisplaySlot, scale: f32, max_width: f32) -> (f32, f32) {
+        let mut width: f32 = 0.0;
+        let mut height = 0.0;
+        for (line, style) in styled_lines(&self.settings, slot) {
+            let line_scale = scale * style.scale;
+            width = width.max(measure_line(line, &self.font, line_scale).min(max_width));
+            height += self.font.line_height * line_scale;
+        }
+        (width, height)
+    }
+
+    fn update_display_state(&mut self, dt: f32) {
+        let mut title_changed = false;
+        let mut artist_changed = false;
+        let mut status_changed = false;
+        let mut art_changed = false;
+
+        if self.display.current_media != self.media.info {
+            title_changed = self.display.current_media.title != self.media.info.title;
+            artist_changed = self.display.current_media.artist != self.media.info.artist;
+            status_changed = self.display.current_media.status != self.media.info.status;
+            art_changed = self.display.current_media.art_url != self.media.info.art_url;
+            self.display.current_media = self.media.info.clone();
+            if art_changed {
+                self.load_current_art();
+            }
+
+            // Layout needs the widths, so fallback glyphs must exist before the
+            // text is formatted.
+            let media = &self.display.current_media;
+            for text in [&media.title, &media.artist] {
+                self.ttf
+                    .add_missing_glyphs(text, &mut self.font, &mut self.atlas);
+            }
+        }
+
+        let mut should_update = if self.settings.show_playback_status {
+            title_changed || artist_changed || status_changed
+        } else {
+            title_changed || artist_changed
+        };
+
+        if !should_update
+            && !self.settings.show_playback_status
+            && status_changed
+            && self.media.info.status == MediaStatus::Playing
+        {
+            should_update = true;
+        }
+
+        let primary_index = self.display.primary_index;
+        if !self.settings.show_playback_status
+            && should_update
+            && self.media.info.status != MediaStatus::Playing
+            && self.display.slots[primary_index].state == DisplayState::Hidden
+        {
+            should_update = false;
+        }
+
+        if should_update {
+            match self.display.slots[primary_index].state {
+                DisplayState::Hidden => {
+                    swap_and_show(&mut self.display, &self.settings, &self.font)
+                }
+                DisplayState::Visible => {
+                    if title_changed || artist_changed {
+                        swap_and_show(&mut self.display, &self.settings, &self.font)
+                    }
+                }
+                DisplayState::Disappearing => {
+                    swap_and_show(&mut self.display, &self.settings, &self.font)
+                }
+                DisplayState::AppearingDelay | DisplayState::Appearing => {}
+            }
+        }
+
+        if art_changed {
+            self.show_current_art();
+        }
+
+        for slot in self.display.slots.iter_mut() {
+            update_display_slot(slot, &self.settings, &self.media, dt);
+        }
+    }
+}
+
+/// Wakes a backend that is blocked in `PresentationBackend::wait` from another
+/// thread.
+pub(crate) type Waker = Box<dyn Fn() + Send>;
+
+/// Puts frames rendered by `OverlayCore` on screen.
+///
+/// The wait methods return whether the backend itself wants a new frame, e.g.
+/// because a surface was configured or moved to an output with another scale.
+pub(crate) trait PresentationBackend {
+    /// Shows the current state of `core`.
+    fn present(&mut self, core: &OverlayCore) -> Result<()>;
+
+    /// Blocks until the next animation frame is due.
+    fn wait_for_frame(&mut self) -> Result<bool>;
+
+    /// Blocks for at most `timeout`, or until the waker fires.
+    fn wait(&mut self, timeout: Duration) -> Result<bool>;
+
+    fn waker(&self) -> Waker;
+
+    fn is_open(&self) -> bool {
+        true
+    }
+}
+
+/// Runs the overlay until the backend goes away. Animations are presented
+/// every frame; otherwise the loop sleeps until a media update, a settings
+/// change or the next display timeout.
+pub(crate) fn run_overlay(
+    core: &mut OverlayCore,
+    backend: &mut dyn PresentationBackend,
+    media_rx: Receiver<MediaInfo>,
+) -> Result<()> {
+    core.art_loader = Some(ArtLoader::spawn(backend.waker()));
+    let waker = backend.waker();
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        for info in media_rx {
+            if tx.send(info).is_err() {
+                break;
+            }
+            waker();
+        }
+    });
+
+    let mut redraw = true;
+    while backend.is_open() {
+        for info in rx.try_iter() {
+            core.set_media(info);
+        }
+        redraw |= core.reload_settings_if_needed();
+        redraw |= core.tick(Instant::now());
+        if redraw {
+            backend.present(core)?;
+        }
+
+        redraw = if core.is_animating() {
+            backend.wait_for_frame()?
+        } else {
+            let timeout = core
+                .next_timeout()
+                .map_or(SETTINGS_CHECK_INTERVAL, |timeout| {
+                    timeout.min(SETTINGS_CHECK_INTERVAL)
+                });
+            backend.wait(timeout)?
+        };
+    }
+    Ok(())
+}
+
+/// Decodes album art on a worker thread and hands it back over a channel,
+/// waking the backend like media updates do.
+struct ArtLoader {
+    requests: Sender<ArtRequest>,
+    results: Receiver<LoadedArt>,
+}
+
+struct ArtRequest {
+    generation: u64,
+    url: String,
+    settings: AlbumArt,
+}
+
+struct LoadedArt {
+    generation: u64,
+    art: Option<RgbaImage>,
+}
+
+impl ArtLoader {
+    fn spawn(waker: Waker) -> Self {
+        let (request_tx, request_rx) = mpsc::channel::<ArtRequest>();
+        let (result_tx, result_rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            while let Ok(request) = request_rx.recv() {
+                // When skipping through tracks only the newest art matters.
+                let request = request_rx.try_iter().last().unwrap_or(request);
+                let art = load_album_art(&request.url, &request.settings);
+                let loaded = LoadedArt {
+                    generation: request.generation,
+                    art,
+                };
+                if result_tx.send(loaded).is_err() {
+                    break;
+                }
+                waker();
+            }
+        });
+        Self {
+            requests: request_tx,
+            results: result_rx,
+        }
+    }
+}
+
+fn swap_and_show(controller: &mut DisplayController, settings: &Settings, font: &BitmapFont) {
+    let old_primary = controller.primary_index;
+    let new_primary = 1 - old_primary;
+    controller.primary_index = new_primary;
+
+    let with_art = controller.current_art.is_some();
+    let (text, title_lines) =
+        format_media_text(settings, font, &controller.current_media, with_art);
+    update_slot_text(&mut controller.slots[new_primary], text, title_lines);
+    controller.slots[new_primary].art = controller.current_art.clone();
+
+    // The old text has to get out of the way first, unless it already has.
+    if controller.slots[old_primary].state == DisplayState::Hidden {
+        controller.slots[new_primary].state = DisplayState::Appearing;
+        controller.slots[new_primary].timer = 0.0;
+    } else {
+        if controller.slots[old_primary].state != DisplayState::Disappearing {
+            controller.slots[old_primary].state = DisplayState::Disappearing;
+            controller.slots[old_primary].timer = 0.0;
+        }
+        controller.slots[new_primary].state = DisplayState::AppearingDelay;
+        controller.slots[new_primary].timer = 0.0;
+    }
+}
+
+const SETTINGS_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+pub(crate) const PADDING: f32 = 12.0;
+
+fn update_display_slot(slot: &mut DisplaySlot, settings: &Settings, media: &MediaState, dt: f32) {
+    let animation = &settings.animation;
+    let scale = settings.scale_factor * settings.text_scale;
+    match slot.state {
+        DisplayState::AppearingDelay => {
+            if slot.timer == 0.0 {
+                slot.opacity = 0.0;
+                slot.offset_x = 0.0;
+                slot.offset_y = 0.0;
+            }
+            // Only the typewriter reveals the text glyph by glyph.
+            slot.visible_glyphs = if animation.mode == AnimationMode::Typewriter {
+                0
+            } else {
+                usize::MAX
+            };
+            if slot.timer >= animation.appear_delay {
+                slot.state = DisplayState::Appearing;
+                slot.timer = 0.0;
+            }
+        }
+        DisplayState::Appearing if animation.mode == AnimationMode::Typewriter => {
+            slot.opacity = 1.0;
+            slot.offset_x = 0.0;
+            slot.offset_y = 0.0;
+            let (visible_glyphs, done) = typewriter_progress(&slot.text, animation, slot.timer);
+            slot.visible_glyphs = visible_glyphs;
+            if done {
+                slot.state = DisplayState::Visible;
+                slot.timer = 0.0;
+            }
+        }
+        DisplayState::Appearing => {
+            if slot.timer == 0.0 {
+                slot.opacity = 0.0;
+                slot.offset_x = 0.0;
+                slot.offset_y = 0.0;
+            }
+            slot.visible_glyphs = usize::MAX;
+            let progress = animation_progress(slot.timer, animation.appear_duration);
+            slot.opacity = (progress * 1.5 - 0.25).clamp(0.0, 1.0);
+            // Starts one slide distance behind where the text comes to rest.
+            let distance = animation.slide_in_distance
+                * scale
+                * (1.0 - ease(animation.appear_easing, progress));
+            (slot.offset_x, slot.offset_y) = slide_offset(animation.appear_direction, -distance);
+            if slot.timer >= animation.appear_duration {
+                slot.state = DisplayState::Visible;
+                slot.timer = 0.0;
+            }
+        }
+        DisplayState::Visible => {
+            if slot.timer == 0.0 {
+                slot.opacity = 1.0;
+                slot.offset_x = 0.0;
+                slot.offset_y = 0.0;
+            }
+            slot.visible_glyphs = usize::MAX;
+            if let Some(hide_after) = settings.hide_automatically {
+                if slot.timer >= hide_after {
+                    slot.state = DisplayState::Disappearing;
+                    slot.timer = 0.0;
+                }
+            } else if !settings.show_playback_status
+                && slot.timer >= animation.stay_time
+                && (media.info.status == MediaStatus::Stopped
+                    || media.info.status == MediaStatus::Paused)
+            {
+                slot.state = DisplayState::Disappearing;
+                slot.timer = 0.0;
+            }
+        }
+        DisplayState::Disappearing => {
+            if slot.timer == 0.0 {
+                slot.opacity = 1.0;
+                slot.offset_x = 0.0;
+                slot.offset_y = 0.0;
+            }
+            slot.visible_glyphs = usize::MAX;
+            let progress = animation_progress(slot.timer, animation.disappear_duration);
+            slot.opacity = ((1.0 - progress) * 1.5 - 0.25).clamp(0.0, 1.0);
+            let distance = animation.slide_out_distance
+                * scale
+                * (1.0 - ease(animation.disappear_easing, 1.0 - progress));
+            (slot.offset_x, slot.offset_y) = slide_offset(animation.disappear_direction, distance);
+            if slot.timer >= animation.disappear_duration {
+                slot.state = DisplayState::Hidden;
+                slot.opacity = 0.0;
+            }
+        }
+        DisplayState::Hidden => {
+            slot.opacity = 0.0;
+            slot.offset_x = 0.0;
+            slot.offset_y = 0.0;
+        }
+    }
+
+    if slot.state != DisplayState::Hidden {
+        slot.timer += dt;
+        slot.marquee_timer += dt;
+    }
+}
+
+fn update_slot_text(slot: &mut DisplaySlot, text: String, title_lines: usize) {
+    slot.title_lines = title_lines;
+    if slot.text == text {
+        return;
+    }
+    slot.text = text;
+    slot.marquee_timer = 0.0;
+}
+
+/// The lines of `slot` with the style each is drawn in.
+fn styled_lines<'a>(
+    settings: &'a Settings,
+    slot: &'a DisplaySlot,
+) -> impl Iterator<Item = (&'a str, &'a TextStyle)> {
+    slot.text.split('\n').enumerate().map(|(index, line)| {
+        let style = if index < slot.title_lines {
+            &settings.title_style
+        } else {
+            &settings.artist_style
+        };
+        (line, style)
+    })
+}
+
+/// Widest a line of text may be in device pixels before it scrolls, so the
+/// canvas stays within `max_width`.
+fn max_text_width(settings: &Settings, with_art: bool, buffer_scale: f64) -> Option<f32> {
+    let max_width = settings.max_width? as f32 * buffer_scale as f32;
+    let padding = PADDING * buffer_scale as f32;
+    let margin = content_margin(
+        settings,
+        device_text_scale(settings, buffer_scale),
+        with_art,
+    );
+    Some((max_width / settings.scale_x - padding * 2.0 - margin.left - margin.right).max(1.0))
+}
+
+/// Room the outline and shadow of either style need around the text, for
+/// text drawn at `scale`.
+fn text_margin(settings: &Settings, scale: f32) -> TextMargin {
+    [&settings.title_style, &settings.artist_style]
+        .into_iter()
+        .map(|style| style.margin().scaled(scale * style.scale))
+        .fold(TextMargin::default(), TextMargin::max)
+}
+
+/// Room everything around the text needs: the outline and shadow, the album
+/// art beside it if there is one, and the panel behind it all.
+pub(crate) fn content_margin(settings: &Settings, scale: f32, with_art: bool) -> TextMargin {
+    let inset = panel_inset(settings, scale);
+    let mut margin = text_margin(settings, scale);
+    if let Some(art) = settings.album_art.as_ref().filter(|_| with_art) {
+        match art.position {
+            ArtPosition::Left => margin.left += art.width() * scale,
+            ArtPosition::Right => margin.right += art.width() * scale,
+        }
+    }
+    TextMargin {
+        left: margin.left + inset,
+        top: margin.top + inset,
+        right: margin.right + inset,
+        bottom: margin.bottom + inset,
+    }
+}
+
+/// Room the panel's border and padding take up on every side.
+pub(crate) fn panel_inset(settings: &Settings, scale: f32) -> f32 {
+    settings.panel.as_ref().map_or(0.0, Panel::inset) * scale
+}
+
+/// How far a line that is `overflow` pixels too wide has scrolled after
+/// `timer` seconds. It rests at the start, scrolls to the end, rests there
+/// and scrolls back.
+fn marquee_offset(settings: &Settings, overflow: f32, timer: f32) -> f32 {
+    if overflow <= 0.0 || settings.marquee_speed <= 0.0 {
+        return 0.0;
+    }
+    let pause = settings.marquee_pause.max(0.0);
+    let travel = overflow / settings.marquee_speed;
+    let t = timer % (2.0 * (pause + travel));
+    if t < pause {
+        0.0
+    } else if t < pause + travel {
+        (t - pause) * settings.marquee_speed
+    } else if t < 2.0 * pause + travel {
+        overflow
+    } else {
+        overflow - (t - 2.0 * pause - travel) * settings.marquee_speed
+    }
+}
+
+/// Glyph scale in device pixels. On scaled outputs the scale factor is rounded
+/// so every font pixel still covers a whole number of device pixels.
+fn device_text_scale(settings: &Settings, buffer_scale: f64) -> f32 {
+    if buffer_scale == 1.0 {
+        return settings.scale_factor * settings.text_scale;
+    }
+    let scale_factor = (settings.scale_factor * buffer_scale as f32)
+        .round()
+        .max(1.0);
+    scale_factor * settings.text_scale
+}
+
+/// How many characters the typewriter shows after `timer` seconds, and whether
+/// it has typed all of them. Like in Deltarune it stops for a moment after
+/// punctuation; line breaks don't count as characters.
+fn typewriter_progress(text: &str, animation: &AnimationSettings, timer: f32) -> (usize, bool) {
+    let total = text.chars().filter(|&ch| ch != '\n').count();
+    if animation.typewriter_speed <= 0.0 {
+        return (total, true);
+    }
+
+    let char_time = 1.0 / animation.typewriter_speed;
+    // The first character shows right away, every other one waits for the
+    // one before it.
+    let mut reveal_at = 0.0;
+    for (visible, ch) in text.chars().filter(|&ch| ch != '\n').enumerate() {
+        if reveal_at > timer {
+            return (visible, false);
+        }
+        reveal_at += char_time;
+        if matches!(ch, '.' | ',' | '!' | '?' | ';' | ':' | '…') {
+            reveal_at += animation.typewriter_pause;
+        }
+    }
+    (total, true)
+}
+
+/// How far `timer` is into an animation of `duration` seconds, from 0 to 1.
+/// A zero duration finishes at once instead of dividing by zero.
+fn animation_progress(timer: f32, duration: f32) -> f32 {
+    if duration <= 0.0 {
+        return 1.0;
+    }
+    (timer / duration).clamp(0.0, 1.0)
+}
+
+/// Ease-out curve from 0 at `t = 0` to 1 at `t = 1`. Back and elastic
+/// overshoot in between.
+fn ease(easing: Easing, t: f32) -> f32 {
+    const STEPS: f32 = 4.0;
+    const BACK_OVERSHOOT: f32 = 1.70158;
+
+    let t = t.clamp(0.0, 1.0);
+    match easing {
+        Easing::Linear => t,
+        Easing::Quadratic => 1.0 - (1.0 - t).powi(2),
+        Easing::Cubic => 1.0 - (1.0 - t).powi(3),
+        Easing::Back => {
+            1.0 + (BACK_OVERSHOOT + 1.0) * (t - 1.0).powi(3) + BACK_OVERSHOOT * (t - 1.0).powi(2)
+        }
+        Easing::Elastic => {
+            if t <= 0.0 || t >= 1.0 {
+                return t;
+            }
+            let period = 2.0 * std::f32::consts::PI / 3.0;
+            2f32.powf(-10.0 * t) * ((t * 10.0 - 0.75) * period).sin() + 1.0
+        }
+        Easing::Steps => (t * STEPS).floor() / STEPS,
+    }
+}
+
+/// Offset of `distance` font pixels along `direction`; negative distances
+/// point the other way.
+fn slide_offset(direction: SlideDirection, distance: f32) -> (f32, f32) {
+    match direction {
+        SlideDirection::Left => (-distance, 0.0),
+        SlideDirection::Right => (distance, 0.0),
+        SlideDirection::Up => (0.0, -distance),
+        SlideDirection::Down => (0.0, distance),
+        SlideDirection::None => (0.0, 0.0),
+    }
+}
+
+/// The text to show for `media`, and how many of its lines are the title.
+/// `with_art` leaves room for the album art when lines are fitted.
+fn format_media_text(
+    settings: &Settings,
+    font: &BitmapFont,
+    media: &MediaInfo,
+    with_art: bool,
+) -> (String, usize) {
+    if media.status == MediaStatus::Stopped {
+        return (String::new(), 0);
+    }
+
+    let mut title = media.title.trim().to_string();
+    let mut artist = media.artist.trim().to_string();
+
+    if artist.ends_with(" - Topic") {
+        artist.truncate(artist.len().saturating_sub(8));
+    }
+
+    if !artist.is_empty() && !title.is_empty() {
+        let prefix = format!("{artist} - ");
+        if title.starts_with(&prefix) {
+            title = title.replacen(&prefix, "", 1);
+        }
+        let suffix = format!(" - {artist}");
+        if title.ends_with(&suffix) {
+            title.truncate(title.len().saturating_sub(suffix.len()));
+        }
+    }
+
+    let mut buffer = String::new();
+    if settings.show_playback_status {
+        let icon = match media.status {
+            MediaStatus::Playing => "♪",
+            MediaStatus::Paused => "⏸",
+            MediaStatus::Stopped => "",
+        };
+        if !icon.is_empty() {
+            buffer.push_str(icon);
+            buffer.push_str("~\u{2009}\u{2009}\u{2009}");
+        }
+    } else if media.status == MediaStatus::Playing {
+        buffer.push_str("♪~\u{2009}\u{2009}\u{2009}");
+    }
+
+    if !title.is_empty() {
+        buffer.push_str(&title);
+    }
+
+    let mut lines = Vec::new();
+    if !buffer.is_empty() {
+        let max_width = settings.title_max_width.or(settings.max_width);
+        let max_lines = settings.title_max_lines;
+        lines.extend(fit_line(
+            &buffer,
+            settings,
+            &settings.title_style,
+            font,
+            max_width,
+            max_lines,
+            with_art,
+        ));
+    }
+    let title_lines = lines.len();
+    if settings.show_artist_name && !artist.is_empty() {
+        let max_width = settings.artist_max_width.or(settings.max_width);
+        let max_lines = settings.artist_max_lines;
+        lines.extend(fit_line(
+            &artist,
+            settings,
+            &settings.artist_style,
+            font,
+            max_width,
+            max_lines,
+            with_art,
+        ));
+    }
+
+    (lines.join("\n"), title_lines)
+}
+
+/// Lays out one line of metadata for `OverflowMode::Truncate` and `Wrap`.
+/// `max_width` is in pixels of the overlay, like `Settings::max_width`.
+fn fit_line(
+    line: &str,
+    settings: &Settings,
+    style: &TextStyle,
+    font: &BitmapFont,
+    max_width: Option<u32>,
+    max_lines: u32,
+    with_art: bool,
+) -> Vec<String> {
+    let base_scale = settings.scale_factor * settings.text_scale;
+    let scale = base_scale * style.scale;
+    let margin = content_margin(settings, base_scale, with_art);
+    let max_width = match max_width {
+        Some(max_width) => {
+            (max_width as f32 / settings.scale_x - PADDING * 2.0 - margin.left - margin.right)
+                .max(1.0)
+        }
+        None => return vec![line.to_string()],
+    };
+    match settings.overflow {
+        OverflowMode::Marquee => vec![line.to_string()],
+        OverflowMode::Truncate => vec![truncate_line(line, font, scale, max_width)],
+        OverflowMode::Wrap => wrap_line(line, font, scale, max_width, max_lines.max(1)),
+    }
+}
+
+/// Cuts `line` at the last glyph that still leaves room for an ellipsis.
+fn truncate_line(line: &str, font: &BitmapFont, scale: f32, max_width: f32) -> String {
+    if measure_line(line, font, scale) <= max_width {
+        return line.to_string();
+    }
+    // Not every bitmap font has the ellipsis character.
+    let ellipsis = if font.glyphs.contains_key(&('…' as u32)) {
+        "…"
+    } else {
+        "..."
+    };
+    let mut width = measure_line(ellipsis, font, scale);
+    let mut end = 0;
+    let mut previous = None;
+    for (index, ch) in line.char_indices() {
+        if let Some(previous) = previous {
+            width += font.kerning(previous, ch) * scale;
+        }
+        width += measure_line(ch.encode_utf8(&mut [0; 4]), font, scale);
+        if width > max_width {
+            break;
+        }
+        end = index + ch.len_utf8();
+        previous = Some(ch);
+    }
+    format!("{}{ellipsis}", line[..end].trim_end())
+}
+
+/// Breaks `line` at whitespace, or between any two CJK characters, into at
+/// most `max_lines` lines. Whatever does not fit into the last line is
+/// truncated.
+fn wrap_line(
+    line: &str,
+    font: &BitmapFont,
+    scale: f32,
+    max_width: f32,
+    max_lines: u32,
+) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut rest = line;
+    while lines.len() + 1 < max_lines as usize && measure_line(rest, font, scale) > max_width {
+        let mut width = 0.0;
+        let mut last_break = None;
+        let mut end = rest.len();
+        let mut previous = None;
+        for (index, ch) in rest.char_indices() {
+            if ch.is_whitespace() || previous.is_some_and(|previous| is_cjk(previous) || is_cjk(ch))
+            {
+                last_break = Some(index);
+            }
+            if let Some(previous) = previous {
+                width += font.kerning(previous, ch) * scale;
+            }
+            width += measure_line(ch.encode_utf8(&mut [0; 4]), font, scale);
+            // A single glyph wider than the line still has to go somewhere.
+            if width > max_width && index > 0 {
+                end = index;
+                break;
+            }
+            previous = Some(ch);
+        }
+        let split = last_break.filter(|&index| index > 0).unwrap_or(end);
+        lines.push(rest[..split].trim_end().to_string());
+        rest = rest[split..].trim_start();
+    }
+    lines.push(truncate_line(rest, font, scale, max_width));
+    lines
+}
+
+/// Scripts that are written without spaces and may break between any two
+/// characters.
+fn is_cjk(ch: char) -> bool {
+    matches!(ch as u32,
+        0x1100..=0x11FF // Hangul Jamo
+        | 0x2E80..=0x9FFF // CJK radicals, kana and unified ideographs
+        | 0xAC00..=0xD7AF // Hangul syllables
+        | 0xF900..=0xFAFF // CJK compatibility ideographs
+        | 0xFF00..=0xFFEF // Fullwidth forms
+        | 0x20000..=0x2FFFF // CJK extensions
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OverflowMode;
+
+    #[test]
+    fn refits_text_when_art_arrives_late() {
+        crate::assets::use_builtin_assets_only();
+        let dir = std::env::temp_dir().join(format!("deltatune-art-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let cover = dir.join("cover.png");
+        RgbaImage::from_pixel(8, 8, image::Rgba([255, 0, 0, 255]))
+            .save(&cover)
+            .unwrap();
+
+        let settings = Settings {
+            overflow: OverflowMode::Truncate,
+            max_width: Some(900),
+            album_art: Some(AlbumArt::default()),
+            ..Settings::default()
+        };
+        let settings_path = dir.join("Settings.json");
+        let (font, atlas) = load_assets(&settings.fonts);
+        let settings_state = SettingsState::new(&settings_path);
+        let mut core = OverlayCore::new(settings_path, settings, settings_state, font, atlas);
+        let (wake_tx, wake_rx) = mpsc::channel();
+        core.art_loader = Some(ArtLoader::spawn(Box::new(move || {
+            let _ = wake_tx.send(());
+        })));
+
+        let media = MediaInfo {
+            title: "Field of Hopes and Dreams, but with a title far too long for one line"
+                .to_string(),
+            artist: "Toby Fox".to_string(),
+            status: MediaStatus::Playing,
+            art_url: format!("file://{}", cover.display()),
+        };
+        let fitted = |with_art| format_media_text(&core.settings, &core.font, &media, with_art).0;
+        let (without_art, with_art) = (fitted(false), fitted(true));
+        assert_ne!(without_art, with_art);
+
+        let start = Instant::now();
+        core.set_media(media.clone());
+        core.tick(start);
+        let primary = core.display.primary_index;
+        assert!(core.display.slots[primary].art.is_none());
+        assert_eq!(core.display.slots[primary].text, without_art);
+
+        wake_rx.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert!(core.tick(start + Duration::from_millis(16)));
+        let slot = &core.display.slots[primary];
+        assert_eq!(
+            slot.art.as_ref().map(|art| art.dimensions()),
+            Some((32, 32))
+        );
+        assert_eq!(slot.text, with_art);
+        assert_eq!(slot.state, DisplayState::Appearing);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,136 @@
+use crate::assets::resolve_asset;
+use crate::{blend_pixel, Color, Panel};
+use image::RgbaImage;
+
+/// Where a panel goes on the canvas, in device pixels.
+pub(crate) struct PanelRect {
+    pub(crate) x: f32,
+    pub(crate) y: f32,
+    pub(crate) width: f32,
+    pub(crate) height: f32,
+}
+
+/// Loads the 9-slice frame `panel` asks for, if any.
+pub(crate) fn load_panel_frame(panel: Option<&Panel>) -> Option<RgbaImage> {
+    let name = panel?.frame.as_deref()?;
+    let Some(asset) = resolve_asset(name) else {
+        eprintln!("Panel frame {name} not found");
+        return None;
+    };
+    let image = asset
+        .read()
+        .map_err(anyhow::Error::from)
+        .and_then(|data| Ok(image::load_from_memory(&data)?));
+    match image {
+        Ok(image) => Some(image.to_rgba8()),
+        Err(err) => {
+            eprintln!("Failed to load panel frame {asset}: {err}");
+            None
+        }
+    }
+}
+
+/// Draws `panel` into a BGRA canvas that is `canvas_w` pixels wide, with
+/// font pixels `scale` device pixels big.
+pub(crate) fn draw_panel(
+    canvas: &mut [u8],
+    canvas_w: u32,
+    panel: &Panel,
+    frame: Option<&RgbaImage>,
+    rect: PanelRect,
+    scale: f32,
+    opacity: f32,
+) {
+    let canvas_h = canvas.len() as u32 / 4 / canvas_w.max(1);
+    let x0 = rect.x.round() as i32;
+    let y0 = rect.y.round() as i32;
+    let width = rect.width.round() as i32;
+    let height = rect.height.round() as i32;
+
+    for dy in 0..height {
+        let y = y0 + dy;
+        if y < 0 || y >= canvas_h as i32 {
+            continue;
+        }
+        for dx in 0..width {
+            let x = x0 + dx;
+            if x < 0 || x >= canvas_w as i32 {
+                continue;
+            }
+            let pixel = match frame {
+                Some(frame) => frame_pixel(frame, panel.frame_border, (dx, dy), (width, height), scale),
+                None => box_pixel(panel, (dx, dy), (width, height), scale),
+            };
+            let Some([r, g, b, a]) = pixel else {
+                continue;
+            };
+            let index = ((y as u32 * canvas_w + x as u32) * 4) as usize;
+            blend_pixel(&mut canvas[index..index + 4], r, g, b, a, [1.0; 3], opacity);
+        }
+    }
+}
+
+/// The drawn box, worked out in whole font pixels so the border and the
+/// rounded corners step like the glyphs do.
+fn box_pixel(panel: &Panel, (dx, dy): (i32, i32), (width, height): (i32, i32), scale: f32) -> Option<[u8; 4]> {
+    let columns = (width as f32 / scale).round().max(1.0);
+    let rows = (height as f32 / scale).round().max(1.0);
+    let column = (dx as f32 / scale).floor().min(columns - 1.0) + 0.5;
+    let row = (dy as f32 / scale).floor().min(rows - 1.0) + 0.5;
+
+    let radius = (panel.corner_radius as f32).min(columns / 2.0).min(rows / 2.0);
+    let inside = |inset: f32, radius: f32| {
+        let (left, top, right, bottom) = (inset, inset, columns - inset, rows - inset);
+        if column < left || column > right || row < top || row > bottom {
+            return false;
+        }
+        let corner_x = column.clamp(left + radius, right - radius);
+        let corner_y = row.clamp(top + radius, bottom - radius);
+        (column - corner_x).powi(2) + (row - corner_y).powi(2) <= radius * radius
+    };
+    if !inside(0.0, radius) {
+        return None;
+    }
+
+    let border = panel.border_width as f32;
+    if inside(border, (radius - border).max(0.0)) {
+        let Color { r, g, b } = panel.color;
+        Some([r, g, b, (panel.opacity.clamp(0.0, 1.0) * 255.0).round() as u8])
+    } else {
+        let Color { r, g, b } = panel.border_color;
+        Some([r, g, b, 255])
+    }
+}
+
+/// Samples a 9-slice frame stretched to `width` x `height`. The corners keep
+/// their size, the edges stretch along one axis and the middle along both.
+fn frame_pixel(
+    frame: &RgbaImage,
+    border: u32,
+    (dx, dy): (i32, i32),
+    (width, height): (i32, i32),
+    scale: f32,
+) -> Option<[u8; 4]> {
+    let border = border.min(frame.width() / 2).min(frame.height() / 2);
+    let x = slice_coordinate(dx, width, frame.width(), border, scale);
+    let y = slice_coordinate(dy, height, frame.height(), border, scale);
+    let pixel = frame.get_pixel(x, y).0;
+    (pixel[3] > 0).then_some(pixel)
+}
+
+/// Maps a device pixel `position` along a panel edge of `length` to a pixel
+/// of a frame edge of `source_length`.
+fn slice_coordinate(position: i32, length: i32, source_length: u32, border: u32, scale: f32) -> u32 {
+    let device_border = border as f32 * scale;
+    let position = position as f32 + 0.5;
+    let length = length as f32;
+    let source = if position < device_border {
+        position / scale
+    } else if position >= length - device_border {
+        source_length as f32 - (length - position) / scale
+    } else {
+        let middle = (length - 2.0 * device_border).max(1.0);
+        border as f32 + (position - device_border) / middle * (source_length - 2 * border) as f32
+    };
+    (source.floor().max(0.0) as u32).min(source_length - 1)
+}
@@ -0,0 +1,169 @@
+use crate::{BitmapFont, FontAtlas, FontAtlasPage, Glyph, Settings};
+use ab_glyph::{point, Font, FontVec, PxScale, ScaleFont};
+use std::collections::HashSet;
+use std::fs;
+
+/// Width of the atlas page TrueType glyphs are rasterized into. The page grows
+/// downwards when it fills up.
+const PAGE_WIDTH: u32 = 512;
+/// Empty pixels between glyphs so scaling never samples a neighbour.
+const GLYPH_GAP: u32 = 1;
+
+/// Last resort for codepoints none of the bitmap fonts have. Glyphs are
+/// rasterized on demand from the `ttf_fallback_fonts` at the bitmap font's
+/// line height, so they get scaled up in whole pixels like the rest of the
+/// text.
+pub(crate) struct TtfFallback {
+    fonts: Vec<FontVec>,
+    threshold: Option<f32>,
+    pixel_snap: bool,
+    page: Option<u32>,
+    cursor_x: u32,
+    cursor_y: u32,
+    row_height: u32,
+    /// Codepoints that no fallback font has either, so they are not looked
+    /// up again for every song.
+    missing: HashSet<u32>,
+}
+
+impl TtfFallback {
+    pub(crate) fn new(settings: &Settings) -> Self {
+        let fonts = settings
+            .ttf_fallback_fonts
+            .iter()
+            .filter_map(|path| {
+                let data = fs::read(path)
+                    .map_err(|err| eprintln!("Failed to read fallback font {}: {err}", path.display()))
+                    .ok()?;
+                FontVec::try_from_vec(data)
+                    .map_err(|err| eprintln!("Failed to parse fallback font {}: {err}", path.display()))
+                    .ok()
+            })
+            .collect();
+
+        Self {
+            fonts,
+            threshold: settings.ttf_threshold,
+            pixel_snap: settings.ttf_pixel_snap,
+            page: None,
+            cursor_x: 0,
+            cursor_y: 0,
+            row_height: 0,
+            missing: HashSet::new(),
+        }
+    }
+
+    /// Adds a glyph to `font` for every character of `text` it lacks and one of
+    /// the fallback fonts has.
+    pub(crate) fn add_missing_glyphs(&mut self, text: &str, font: &mut BitmapFont, atlas: &mut FontAtlas) {
+        if self.fonts.is_empty() {
+            return;
+        }
+        for ch in text.chars() {
+            let codepoint = ch as u32;
+            if ch.is_control() || font.glyphs.contains_key(&codepoint) || self.missing.contains(&codepoint) {
+                continue;
+            }
+            match self.rasterize(ch, font.line_height, atlas) {
+                Some(glyph) => {
+                    font.glyphs.insert(codepoint, glyph);
+                }
+                None => {
+                    self.missing.insert(codepoint);
+                }
+            }
+        }
+    }
+
+    fn rasterize(&mut self, ch: char, line_height: f32, atlas: &mut FontAtlas) -> Option<Glyph> {
+        let ttf = self.fonts.iter().find(|ttf| ttf.glyph_id(ch).0 != 0)?;
+        let scaled = ttf.as_scaled(PxScale::from(line_height));
+        let id = ttf.glyph_id(ch);
+
+        let mut baseline = scaled.ascent();
+        let mut x_advance = scaled.h_advance(id);
+        if self.pixel_snap {
+            baseline = baseline.round();
+            x_advance = x_advance.round();
+        }
+        let outline = ttf.outline_glyph(id.with_scale_and_position(scaled.scale(), point(0.0, baseline)));
+
+        let Some(outline) = outline else {
+            // Blank glyphs like spaces only advance the cursor.
+            return Some(Glyph {
+                x: 0.0,
+                y: 0.0,
+                width: 0.0,
+                height: 0.0,
+                x_offset: 0.0,
+                y_offset: 0.0,
+                x_advance,
+                page: 0,
+            });
+        };
+        let bounds = outline.px_bounds();
+        let (width, height) = (bounds.width() as u32, bounds.height() as u32);
+        let (page_id, x, y) = self.allocate(width, height, atlas)?;
+
+        let threshold = self.threshold;
+        let page = atlas.pages.get_mut(&page_id)?;
+        outline.draw(|glyph_x, glyph_y, coverage| {
+            let coverage = match threshold {
+                Some(threshold) if coverage >= threshold => 1.0,
+                Some(_) => 0.0,
+                None => coverage,
+            };
+            let index = (((y + glyph_y) * page.width + x + glyph_x) * 4) as usize;
+            page.pixels[index..index + 4].copy_from_slice(&[255, 255, 255, (coverage * 255.0).round() as u8]);
+        });
+
+        Some(Glyph {
+            x: x as f32,
+            y: y as f32,
+            width: width as f32,
+            height: height as f32,
+            x_offset: bounds.min.x,
+            y_offset: bounds.min.y,
+            x_advance,
+            page: page_id,
+        })
+    }
+
+    /// Finds room for a `width` x `height` glyph on the fallback page, creating
+    /// or growing it as needed.
+    fn allocate(&mut self, width: u32, height: u32, atlas: &mut FontAtlas) -> Option<(u32, u32, u32)> {
+        if width > PAGE_WIDTH {
+            return None;
+        }
+        let page_id = *self.page.get_or_insert_with(|| {
+            let id = atlas.next_page_id();
+            atlas.pages.insert(
+                id,
+                FontAtlasPage {
+                    pixels: vec![0; (PAGE_WIDTH * PAGE_WIDTH * 4) as usize],
+                    width: PAGE_WIDTH,
+                    height: PAGE_WIDTH,
+                },
+            );
+            id
+        });
+
+        if self.cursor_x + width > PAGE_WIDTH {
+            self.cursor_x = 0;
+            self.cursor_y += self.row_height + GLYPH_GAP;
+            self.row_height = 0;
+        }
+
+        let page = atlas.pages.get_mut(&page_id)?;
+        if self.cursor_y + height > page.height {
+            let new_height = (page.height * 2).max(self.cursor_y + height);
+            page.pixels.resize((page.width * new_height * 4) as usize, 0);
+            page.height = new_height;
+        }
+
+        let position = (page_id, self.cursor_x, self.cursor_y);
+        self.cursor_x += width + GLYPH_GAP;
+        self.row_height = self.row_height.max(height);
+        Some(position)
+    }
+}
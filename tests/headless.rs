@@ -0,0 +1,120 @@
+//! Golden image tests for the headless renderer. Every case renders one frame
+//! with fixed settings and the built-in fonts only, and compares it with the
+//! reference image in `tests/golden`. Run with `DELTATUNE_BLESS=1` to write new
+//! reference images after an intentional rendering change.
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+const SONG: &str = r#"0 {"title": "Field of Hopes and Dreams", "artist": "Toby Fox"}"#;
+
+/// Renders the frame at `at` seconds and checks it against `golden/<name>.png`.
+fn assert_golden(name: &str, settings: &str, script: &str, at: f64) {
+    let dir = std::env::temp_dir().join(format!("deltatune-golden-{}-{name}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let settings_path = dir.join("Settings.json");
+    let script_path = dir.join("events.txt");
+    let out = dir.join("frame.png");
+    fs::write(&settings_path, settings).unwrap();
+    fs::write(&script_path, script).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_deltatune_layershell"))
+        .arg("--settings-path")
+        .arg(&settings_path)
+        .arg("--builtin-assets")
+        .arg("--headless")
+        .arg("--script")
+        .arg(&script_path)
+        .arg("--at")
+        .arg(at.to_string())
+        .arg("--out")
+        .arg(&out)
+        .current_dir(&dir)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "headless render failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let golden = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{name}.png"));
+    if std::env::var_os("DELTATUNE_BLESS").is_some() {
+        fs::copy(&out, &golden).unwrap();
+    } else {
+        let actual = image::open(&out).unwrap().to_rgba8();
+        let expected = image::open(&golden)
+            .unwrap_or_else(|err| panic!("Failed to open {}: {err}", golden.display()))
+            .to_rgba8();
+        assert_eq!(
+            actual.dimensions(),
+            expected.dimensions(),
+            "{name} has the wrong size, see {}",
+            out.display()
+        );
+        let differing = actual
+            .pixels()
+            .zip(expected.pixels())
+            .filter(|(a, b)| a != b)
+            .count();
+        assert_eq!(
+            differing,
+            0,
+            "{name} differs in {differing} pixels, see {}",
+            out.display()
+        );
+    }
+    // Failed renders are kept around for comparing.
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn default_settings() {
+    assert_golden("default", "{}", SONG, 2.0);
+}
+
+#[test]
+fn mid_slide() {
+    assert_golden("mid_slide", "{}", SONG, 0.15);
+}
+
+#[test]
+fn mid_vertical_slide() {
+    let settings = r#"{"animation": {"appear_direction": "down"}}"#;
+    assert_golden("mid_vertical_slide", settings, SONG, 0.2);
+}
+
+#[test]
+fn styled_dialog_box() {
+    let settings = r##"{
+        "background_opacity": 0.0,
+        "title_style": {
+            "color": "#ffff00",
+            "gradient": "#ff4000",
+            "outline": {"width": 1, "color": "#000000"}
+        },
+        "artist_style": {
+            "color": "#80c0ff",
+            "scale": 0.666,
+            "shadow": {"offset_x": 1, "offset_y": 1, "color": "#000000", "opacity": 1.0}
+        },
+        "panel": {"corner_radius": 3, "border_color": "#ff0000"}
+    }"##;
+    assert_golden("styled_dialog_box", settings, SONG, 2.0);
+}
+
+#[test]
+fn fallback_fonts() {
+    let script = r#"0 {"title": "ビッグ・ショット", "artist": "토비 폭스"}"#;
+    assert_golden("fallback_fonts", "{}", script, 2.0);
+}
+
+#[test]
+fn track_change() {
+    let settings = r#"{"hide_automatically": null}"#;
+    let script = format!("{SONG}\n2 {{\"title\": \"Rude Buster\", \"artist\": \"Toby Fox\"}}");
+    assert_golden("track_change", settings, &script, 4.0);
+}